@@ -1,56 +1,390 @@
-use clap::Parser;
+use clap::{ArgGroup, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::path::PathBuf;
 
 /// CLI client for the Notir WebSocket message server.
 #[derive(Debug, Parser)]
 #[command(name = "notir-cli", version, about)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
     /// Server URL (e.g. ws://localhost:5800)
-    #[arg(long, default_value = "ws://localhost:5800")]
+    #[arg(long, global = true, default_value = "ws://localhost:5800")]
     pub server: String,
 
+    /// Enable verbose/debug logging
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+
+    /// Extra header sent to the server, as `Name: value` (repeatable)
+    #[arg(long = "header", global = true, value_parser = parse_header)]
+    pub headers: Vec<(String, String)>,
+
+    /// Token sent to the server as `Authorization: Bearer <TOKEN>`
+    #[arg(long, global = true)]
+    pub token: Option<String>,
+
+    /// Send --token as this query parameter instead of a header
+    #[arg(long, global = true, value_name = "NAME", requires = "token")]
+    pub token_query: Option<String>,
+
+    /// PEM file with CA certificates to trust in addition to the system ones
+    #[arg(long, global = true)]
+    pub ca_cert: Option<PathBuf>,
+
+    /// PEM client certificate (chain) for mutual TLS
+    #[arg(long, global = true, requires = "client_key")]
+    pub client_cert: Option<PathBuf>,
+
+    /// PEM private key for --client-cert
+    #[arg(long, global = true, requires = "client_cert")]
+    pub client_key: Option<PathBuf>,
+
+    /// Accept any server certificate. For local testing only
+    #[arg(long, global = true)]
+    pub insecure: bool,
+
+    /// Proxy for server connections, webhooks and script fetch(): http://,
+    /// socks5:// or socks5h:// URL, with optional user:password@. Defaults to
+    /// HTTPS_PROXY / HTTP_PROXY / ALL_PROXY
+    #[arg(long, global = true)]
+    pub proxy: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Subscribe to a channel and output (optionally transformed) messages
+    Sub(Box<SubArgs>),
+    /// Publish a message to a channel
+    Pub(PubArgs),
+    /// Offer local files to a broadcast room and stream them on demand
+    Share(ShareArgs),
+    /// List or download files offered in a broadcast room
+    Fetch(FetchArgs),
+    /// Run a transform script over recorded events, optionally checking the results
+    TestScript(TestScriptArgs),
+    /// Feed a `sub --record` recording through the transform and outputs, or republish it
+    Replay(Box<ReplayArgs>),
+}
+
+// A --config file may declare scripts, webhooks and commands, so it satisfies
+// the groups the related flags require.
+#[derive(Debug, Clone, clap::Args)]
+#[command(group(ArgGroup::new("channels").args(["id", "subs", "config"]).multiple(true).required(true)))]
+#[command(group(ArgGroup::new("scripts").args(["script", "config"]).multiple(true)))]
+#[command(group(ArgGroup::new("webhooks").args(["webhook_url", "route_webhook", "config"]).multiple(true)))]
+#[command(group(ArgGroup::new("exec_hooks").args(["exec", "route_exec", "config"]).multiple(true)))]
+pub struct SubArgs {
     /// User/channel ID to subscribe as
     #[arg(long)]
-    pub id: String,
+    pub id: Option<String>,
 
     /// Subscription mode: single (point-to-point) or broad (broadcast)
     #[arg(short, long, default_value = "single")]
     pub mode: SubscriptionMode,
 
+    /// Another channel to follow in the same process, as MODE:ID, e.g.
+    /// broad:room1 (repeatable). Uses the same options as --id
+    #[arg(long = "sub", value_name = "MODE:ID", value_parser = parse_subscription)]
+    pub subs: Vec<(SubscriptionMode, String)>,
+
+    /// TOML file listing subscriptions, each with optional script and output
+    /// settings that override the command line
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
     /// Path to JS transform script. Without this, raw text is passed through.
     #[arg(short, long)]
     pub script: Option<String>,
 
-    /// Output mode: stdout, file, or both
+    /// Reload the script when it (or a module it imports) changes; a version
+    /// that fails to load is logged and the running one is kept
+    #[arg(long, requires = "scripts")]
+    pub watch_script: bool,
+
+    /// Time limit in milliseconds for each transform/reply call, including
+    /// awaited timers and fetches
+    #[arg(long, default_value = "5000", requires = "scripts")]
+    pub script_timeout_ms: u64,
+
+    /// Host the script may reach with fetch(), e.g. api.example.com,
+    /// *.example.com or host:port (repeatable). fetch() is disabled without one
+    #[arg(long = "fetch-allow", value_name = "HOST", requires = "scripts")]
+    pub fetch_allow: Vec<String>,
+
+    /// File the script's `state` store is loaded from and saved to, so it
+    /// survives restarts
+    #[arg(long)]
+    pub state_file: Option<PathBuf>,
+
+    /// Append every received message to this file as a JSON line, for
+    /// `replay` and `test-script`
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+
+    /// Output mode: stdout, file, both, or webhook
     #[arg(short, long, default_value = "stdout")]
     pub output: OutputMode,
 
     /// Output directory for file mode
-    #[arg(short, long, default_value = "./output")]
+    #[arg(long, default_value = "./output")]
     pub output_dir: String,
 
+    /// How binary payloads appear in `e.binary` and JSONL output
+    #[arg(long, default_value = "hex")]
+    pub binary_encoding: BinaryEncoding,
+
+    /// Write untransformed binary messages as raw bytes (no newline) to stdout and append files
+    #[arg(long)]
+    pub raw_binary: bool,
+
+    /// Output format: the transform result as text, or one JSON object per message
+    #[arg(long, default_value = "text")]
+    pub format: OutputFormat,
+
     /// File writing mode: append to single file or one file per message
     #[arg(long, default_value = "append")]
     pub file_mode: FileMode,
 
+    /// File name template. Append mode: `{id}`, `{date}`, `{hour}` [default: {id}.txt].
+    /// Individual mode adds `{seq}`, `{timestamp}`, `{type}`, `{ext}` [default: {seq}.{ext}]
+    #[arg(long)]
+    pub file_name: Option<String>,
+
+    /// Rotate the append-mode file once it reaches this size (e.g. 512K, 100M, 1G)
+    #[arg(long, value_parser = parse_size)]
+    pub rotate_size: Option<u64>,
+
+    /// Rotate hourly or daily (UTC); individual mode uses one directory per period
+    #[arg(long)]
+    pub rotate_every: Option<RotateEvery>,
+
+    /// Rotated files (or period directories) to keep; 0 keeps all
+    #[arg(long, default_value_t = 0)]
+    pub max_files: usize,
+
+    /// Gzip rotated files
+    #[arg(long)]
+    pub compress: bool,
+
     /// Enable auto-reconnect on disconnect
     #[arg(long)]
     pub reconnect: bool,
 
-    /// Reconnect interval in seconds
+    /// Delay before the first reconnect attempt in seconds; doubles with each
+    /// further attempt, with jitter
     #[arg(long, default_value_t = 3)]
     pub reconnect_interval: u64,
 
-    /// Max reconnect attempts (0 = unlimited)
+    /// Longest delay between reconnect attempts in seconds
+    #[arg(long, default_value_t = 60)]
+    pub reconnect_max_interval: u64,
+
+    /// Seconds a connection must stay up for the attempt count to start over
+    #[arg(long, default_value_t = 60)]
+    pub stable_after: u64,
+
+    /// Max reconnect attempts in a row (0 = unlimited)
     #[arg(long, default_value_t = 5)]
     pub max_reconnect: u32,
 
-    /// Enable verbose/debug logging
+    /// Treat the connection as dead when nothing, not even a ping, arrives
+    /// from the server for this many seconds (0 = never). The server pings every 30s
+    #[arg(long, default_value_t = 75)]
+    pub ping_timeout: u64,
+
+    /// URL each message is POSTed to in webhook output mode
+    #[arg(long, required_if_eq("output", "webhook"))]
+    pub webhook_url: Option<String>,
+
+    /// Extra webhook request header as `Name: value` (repeatable)
+    #[arg(long = "webhook-header", value_parser = parse_header, requires = "webhooks")]
+    pub webhook_headers: Vec<(String, String)>,
+
+    /// Named webhook for `webhook:NAME` transform targets, as NAME=URL
+    /// (repeatable); spools to <output-dir>/<id>.<NAME>.spool
+    #[arg(long = "route-webhook", value_name = "NAME=URL", value_parser = parse_route)]
+    pub route_webhook: Vec<(String, String)>,
+
+    /// Webhook retries before a message is spooled to disk
+    #[arg(long, default_value_t = 5, requires = "webhooks")]
+    pub webhook_retries: u32,
+
+    /// Delay before the first webhook retry in milliseconds; doubles each retry
+    #[arg(long, default_value_t = 500, requires = "webhooks")]
+    pub webhook_backoff_ms: u64,
+
+    /// Spool file for undeliverable webhook messages [default: <output-dir>/<id>.spool]
+    #[arg(long, requires = "webhook_url")]
+    pub webhook_spool: Option<PathBuf>,
+
+    /// Shell command run for each transformed message (payload on stdin,
+    /// NOTIR_ID / NOTIR_TYPE / NOTIR_TIMESTAMP in the environment)
+    #[arg(long)]
+    pub exec: Option<String>,
+
+    /// Named command for `exec:NAME` transform targets, as NAME=COMMAND (repeatable)
+    #[arg(long = "route-exec", value_name = "NAME=COMMAND", value_parser = parse_route)]
+    pub route_exec: Vec<(String, String)>,
+
+    /// Max number of commands running at once, per --exec/--route-exec command
+    #[arg(long, default_value_t = 1, requires = "exec_hooks")]
+    pub exec_concurrency: usize,
+
+    /// Kill an --exec/--route-exec command after this many seconds
+    #[arg(long, default_value_t = 30, requires = "exec_hooks")]
+    pub exec_timeout: u64,
+
+    /// Max messages waiting per --exec/--route-exec command; further messages are dropped
+    #[arg(long, default_value_t = 100, requires = "exec_hooks")]
+    pub exec_max_queue: usize,
+
+    /// Send the --exec command's stdout back as the ping_pong reply (single mode)
+    #[arg(long, requires = "exec")]
+    pub exec_reply: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct PubArgs {
+    /// User/channel ID to publish to
+    #[arg(long)]
+    pub id: String,
+
+    /// Target mode: single (point-to-point) or broad (broadcast)
+    #[arg(short, long, default_value = "single")]
+    pub mode: SubscriptionMode,
+
+    /// Wait for the subscriber's reply and print it (single mode only)
+    #[arg(long)]
+    pub ping_pong: bool,
+
+    /// Content-Type of the message; text/* and application/json are sent as text frames
+    #[arg(long, default_value = "text/plain")]
+    pub content_type: String,
+
+    /// Read the body from this file instead of the argument or stdin
+    #[arg(long, conflicts_with = "body")]
+    pub file: Option<PathBuf>,
+
+    /// Publish every stdin line as its own message
+    #[arg(long, conflicts_with_all = ["body", "file"])]
+    pub lines: bool,
+
+    /// Message body. Without this or --file, the body is read from stdin.
+    pub body: Option<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ShareArgs {
+    /// Broadcast room ID to offer the files in
+    #[arg(long)]
+    pub room: String,
+
+    /// Files or directories to share (directories are walked recursively)
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// Offer everything as a single ZIP bundle with this name
+    #[arg(long)]
+    pub bundle: Option<String>,
+
+    /// Offer lifetime in seconds (default: until the CLI exits)
+    #[arg(long)]
+    pub ttl: Option<u64>,
+
+    /// Password receivers must provide to download
+    #[arg(long)]
+    pub password: Option<String>,
+
+    /// Withdraw each offer after this many downloads
+    #[arg(long)]
+    pub max_downloads: Option<u32>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct FetchArgs {
+    /// Broadcast room ID to fetch from
+    #[arg(long)]
+    pub room: String,
+
+    /// File IDs or names to download. Without any, offers are listed.
+    pub files: Vec<String>,
+
+    /// Download every offer currently in the room
+    #[arg(long)]
+    pub all: bool,
+
+    /// Stay connected and download new offers as they appear
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Only download offers whose name matches this glob (`*` and `?`)
+    #[arg(long, default_value = "*")]
+    pub pattern: String,
+
+    /// Directory to save downloads into
+    #[arg(long, default_value = ".")]
+    pub output_dir: PathBuf,
+
+    /// Password for protected offers
+    #[arg(long)]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct TestScriptArgs {
+    /// Transform script to run
     #[arg(short, long)]
-    pub verbose: bool,
+    pub script: String,
+
+    /// Events as JSON lines (e.g. saved with `sub --format jsonl`), `-` for stdin
+    #[arg(short, long)]
+    pub input: PathBuf,
+
+    /// Expected results as JSON lines, one per event. Without it, results are printed
+    #[arg(short, long)]
+    pub expected: Option<PathBuf>,
+
+    /// How `binary` fields in the input are encoded
+    #[arg(long, default_value = "hex")]
+    pub binary_encoding: BinaryEncoding,
+
+    /// Time limit in milliseconds for each transform call
+    #[arg(long, default_value = "5000")]
+    pub script_timeout_ms: u64,
+
+    /// Host the script may reach with fetch() (repeatable)
+    #[arg(long = "fetch-allow", value_name = "HOST")]
+    pub fetch_allow: Vec<String>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[derive(Debug, clap::Args)]
+pub struct ReplayArgs {
+    /// Recording made with `sub --record` (or any events as JSON lines), `-` for stdin
+    pub recording: PathBuf,
+
+    /// Keep the recorded gaps between messages instead of replaying as fast as possible
+    #[arg(long)]
+    pub realtime: bool,
+
+    /// Playback speed for --realtime, e.g. 2 for twice as fast
+    #[arg(long, default_value_t = 1.0, requires = "realtime", value_parser = parse_speed)]
+    pub speed: f64,
+
+    /// Publish the messages to --id on --server instead of processing them locally
+    #[arg(long)]
+    pub publish: bool,
+
+    /// Subscriber options used to process the messages; --id and --mode also
+    /// pick the channel for --publish
+    #[command(flatten)]
+    pub sub: SubArgs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SubscriptionMode {
     Single,
     Broad,
@@ -65,11 +399,27 @@ impl fmt::Display for SubscriptionMode {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OutputMode {
     Stdout,
     File,
     Both,
+    Webhook,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Text,
+    Jsonl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryEncoding {
+    Hex,
+    Base64,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
@@ -77,3 +427,215 @@ pub enum FileMode {
     Append,
     Individual,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RotateEvery {
+    Hourly,
+    Daily,
+}
+
+/// Parses a `Name: value` header argument.
+fn parse_header(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected 'Name: value', got '{}'", s))?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(format!("missing header name in '{}'", s));
+    }
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+/// Parses a `NAME=VALUE` route declaration.
+fn parse_route(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected 'NAME=VALUE', got '{}'", s))?;
+    crate::routes::validate_name(name)?;
+    if value.trim().is_empty() {
+        return Err(format!("missing value for route '{}'", name));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Parses a `MODE:ID` subscription.
+fn parse_subscription(s: &str) -> Result<(SubscriptionMode, String), String> {
+    let (mode, id) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected 'MODE:ID', got '{}'", s))?;
+    let mode = <SubscriptionMode as clap::ValueEnum>::from_str(mode, true)
+        .map_err(|_| format!("unknown mode '{}' (expected single or broad)", mode))?;
+    if id.is_empty() {
+        return Err(format!("missing channel id in '{}'", s));
+    }
+    Ok((mode, id.to_string()))
+}
+
+/// Parses a positive playback speed.
+fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed.is_finite() && speed > 0.0 => Ok(speed),
+        _ => Err(format!("invalid speed '{}': expected a positive number", s)),
+    }
+}
+
+/// Parses a byte size such as `4096`, `512K`, `100M` or `1G` (binary units).
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let upper = s.to_ascii_uppercase();
+    let digits = upper.trim_end_matches('B').trim_end_matches('I');
+    let (number, multiplier) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 1u64 << 10),
+        Some('M') => (&digits[..digits.len() - 1], 1 << 20),
+        Some('G') => (&digits[..digits.len() - 1], 1 << 30),
+        _ => (digits, 1),
+    };
+    let number: u64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", s))?;
+    match number.checked_mul(multiplier) {
+        Some(0) | None => Err(format!("invalid size '{}'", s)),
+        Some(size) => Ok(size),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn global_flags_work_after_subcommand() {
+        let cli = Cli::parse_from([
+            "notir-cli",
+            "sub",
+            "--id",
+            "u1",
+            "--server",
+            "ws://example:1",
+            "-v",
+        ]);
+        assert_eq!(cli.server, "ws://example:1");
+        assert!(cli.verbose);
+        let Command::Sub(args) = cli.command else {
+            panic!("expected sub");
+        };
+        assert_eq!(args.id.as_deref(), Some("u1"));
+        assert_eq!(args.mode, SubscriptionMode::Single);
+    }
+
+    #[test]
+    fn sub_needs_a_channel() {
+        assert!(Cli::try_parse_from(["notir-cli", "sub"]).is_err());
+        let cli = Cli::parse_from([
+            "notir-cli",
+            "sub",
+            "--sub",
+            "single:abc",
+            "--sub",
+            "broad:room1",
+        ]);
+        let Command::Sub(args) = cli.command else {
+            panic!("expected sub");
+        };
+        assert_eq!(
+            args.subs,
+            vec![
+                (SubscriptionMode::Single, "abc".to_string()),
+                (SubscriptionMode::Broad, "room1".to_string())
+            ]
+        );
+        assert!(parse_subscription("room1").is_err());
+        assert!(parse_subscription("multi:room1").is_err());
+    }
+
+    #[test]
+    fn pub_body_sources_are_exclusive() {
+        let cli = Cli::parse_from(["notir-cli", "pub", "--id", "u1", "--ping-pong", "hello"]);
+        let Command::Pub(args) = cli.command else {
+            panic!("expected pub");
+        };
+        assert!(args.ping_pong);
+        assert_eq!(args.body.as_deref(), Some("hello"));
+        assert_eq!(args.content_type, "text/plain");
+
+        assert!(Cli::try_parse_from(["notir-cli", "pub", "--id", "u1", "--lines", "x"]).is_err());
+        assert!(Cli::try_parse_from(["notir-cli", "--id", "u1"]).is_err());
+    }
+
+    #[test]
+    fn webhook_output_needs_url() {
+        assert!(Cli::try_parse_from(["notir-cli", "sub", "--id", "u1", "-o", "webhook"]).is_err());
+        let cli = Cli::parse_from([
+            "notir-cli",
+            "sub",
+            "--id",
+            "u1",
+            "-o",
+            "webhook",
+            "--webhook-url",
+            "http://hook",
+            "--webhook-header",
+            "Authorization: Bearer abc",
+        ]);
+        let Command::Sub(args) = cli.command else {
+            panic!("expected sub");
+        };
+        assert_eq!(
+            args.webhook_headers,
+            vec![("Authorization".to_string(), "Bearer abc".to_string())]
+        );
+        assert!(parse_header("no-colon").is_err());
+    }
+
+    #[test]
+    fn replay_takes_subscriber_options() {
+        let cli = Cli::parse_from([
+            "notir-cli",
+            "replay",
+            "session.jsonl",
+            "--id",
+            "u1",
+            "--realtime",
+            "--speed",
+            "2",
+            "-o",
+            "file",
+        ]);
+        let Command::Replay(args) = cli.command else {
+            panic!("expected replay");
+        };
+        assert_eq!(args.recording, PathBuf::from("session.jsonl"));
+        assert_eq!(args.speed, 2.0);
+        assert_eq!(args.sub.output, OutputMode::File);
+        assert!(
+            Cli::try_parse_from([
+                "notir-cli",
+                "replay",
+                "s.jsonl",
+                "--id",
+                "u1",
+                "--speed",
+                "2"
+            ])
+            .is_err()
+        );
+        assert!(parse_speed("0").is_err());
+    }
+
+    #[test]
+    fn sizes_use_binary_units() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_size("100MiB"), Ok(100 * 1024 * 1024));
+        assert_eq!(parse_size("1g"), Ok(1 << 30));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("ten").is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+/// Reconnect delays: exponential from `initial` up to `max`, with jitter so
+/// many clients dropped at once do not reconnect in lockstep. A connection
+/// that stayed up for `stable` starts the count over.
+#[derive(Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    stable: Duration,
+    attempts: u32,
+    connected_at: Option<Instant>,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration, stable: Duration) -> Self {
+        Self {
+            initial,
+            max: max.max(initial),
+            stable,
+            attempts: 0,
+            connected_at: None,
+        }
+    }
+
+    pub fn connected(&mut self) {
+        self.connected_at = Some(Instant::now());
+    }
+
+    /// Records a lost or failed connection; returns the attempt number of
+    /// the reconnect that follows.
+    pub fn disconnected(&mut self) -> u32 {
+        if self
+            .connected_at
+            .take()
+            .is_some_and(|at| at.elapsed() >= self.stable)
+        {
+            self.attempts = 0;
+        }
+        self.attempts += 1;
+        self.attempts
+    }
+
+    /// Delay before the current attempt: between half and all of the capped
+    /// exponential delay.
+    pub fn delay(&self) -> Duration {
+        self.capped().mul_f64(0.5 + 0.5 * random_fraction())
+    }
+
+    fn capped(&self) -> Duration {
+        let doublings = self.attempts.saturating_sub(1).min(30);
+        self.initial.saturating_mul(1 << doublings).min(self.max)
+    }
+}
+
+/// A number in `[0, 1)`; `RandomState` is randomly keyed, which is plenty for jitter.
+fn random_fraction() -> f64 {
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_double_up_to_the_cap_and_reset_after_a_stable_connection() {
+        let secs = Duration::from_secs;
+        let mut backoff = Backoff::new(secs(1), secs(10), Duration::ZERO);
+        let mut capped = Vec::new();
+        for _ in 0..6 {
+            backoff.disconnected();
+            capped.push(backoff.capped().as_secs());
+            let delay = backoff.delay();
+            assert!(delay >= backoff.capped() / 2 && delay <= backoff.capped());
+        }
+        assert_eq!(capped, [1, 2, 4, 8, 10, 10]);
+
+        backoff.connected();
+        assert_eq!(backoff.disconnected(), 1);
+
+        let mut unstable = Backoff::new(secs(1), secs(10), secs(3600));
+        unstable.disconnected();
+        unstable.connected();
+        assert_eq!(unstable.disconnected(), 2);
+    }
+}
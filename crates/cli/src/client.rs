@@ -0,0 +1,380 @@
+use anyhow::{Context, Result, bail};
+use reqwest::Url;
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::WebPkiSupportedAlgorithms;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
+
+use crate::args::Cli;
+use crate::proxy;
+
+pub type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// How the CLI reaches the notir server: URLs under `--server`, extra headers,
+/// the auth token, TLS and proxy settings, for both WebSocket and HTTP requests.
+#[derive(Clone)]
+pub struct ServerClient {
+    base: Url,
+    headers: HeaderMap,
+    token_query: Option<(String, String)>,
+    /// Custom TLS for WebSockets; `None` uses the system roots.
+    tls: Option<Arc<rustls::ClientConfig>>,
+    /// `--proxy`; without it the environment decides per connection.
+    proxy: Option<Url>,
+    /// The same proxy for reqwest, with `NO_PROXY` applied.
+    http_proxy: Option<reqwest::Proxy>,
+    http: reqwest::Client,
+}
+
+impl ServerClient {
+    pub fn new(cli: &Cli) -> Result<Self> {
+        let base = Url::parse(&cli.server)
+            .with_context(|| format!("Invalid server URL '{}'", cli.server))?;
+        if !matches!(base.scheme(), "ws" | "wss" | "http" | "https") {
+            bail!("Server URL must start with ws://, wss://, http:// or https://");
+        }
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &cli.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name '{}'", name))?;
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid value for header '{}'", name))?;
+            headers.append(name, value);
+        }
+        let mut token_query = None;
+        if let Some(token) = &cli.token {
+            match &cli.token_query {
+                Some(name) => token_query = Some((name.clone(), token.clone())),
+                None => {
+                    let mut value = HeaderValue::from_str(&format!("Bearer {}", token))
+                        .context("Invalid --token")?;
+                    value.set_sensitive(true);
+                    headers.insert(AUTHORIZATION, value);
+                }
+            }
+        }
+
+        let proxy = cli.proxy.as_deref().map(proxy::parse).transpose()?;
+        let http_proxy = proxy
+            .as_ref()
+            .map(|url| {
+                reqwest::Proxy::all(url.as_str())
+                    .map(|p| p.no_proxy(reqwest::NoProxy::from_env()))
+                    .context("Invalid --proxy")
+            })
+            .transpose()?;
+        let tls = TlsFiles::load(cli)?;
+        let mut http = reqwest::Client::builder()
+            .default_headers(headers.clone())
+            .danger_accept_invalid_certs(cli.insecure);
+        // reqwest reads the proxy environment variables itself.
+        if let Some(proxy) = &http_proxy {
+            http = http.proxy(proxy.clone());
+        }
+        for cert in &tls.roots {
+            http = http.add_root_certificate(
+                reqwest::Certificate::from_der(cert).context("Invalid --ca-cert")?,
+            );
+        }
+        if let Some(pem) = &tls.identity_pem {
+            http = http.identity(
+                reqwest::Identity::from_pem(pem).context("Invalid --client-cert/--client-key")?,
+            );
+        }
+        let http = http.build().context("Failed to build HTTP client")?;
+        let tls = if tls.is_default() && !cli.insecure {
+            None
+        } else {
+            Some(Arc::new(tls.rustls_config(cli.insecure)?))
+        };
+
+        Ok(Self {
+            base,
+            headers,
+            token_query,
+            tls,
+            proxy,
+            http_proxy,
+            http,
+        })
+    }
+
+    /// `--proxy` for HTTP calls that do not go to the server: webhooks and
+    /// script `fetch()`. `None` leaves them on the proxy environment variables.
+    pub fn http_proxy(&self) -> Option<reqwest::Proxy> {
+        self.http_proxy.clone()
+    }
+
+    /// `ws://`/`wss://` URL of `path` under the server URL. Segments and
+    /// query values are percent-encoded; the token query is added on connect.
+    pub fn ws_url(&self, path: &[&str], query: &[(&str, &str)]) -> Url {
+        let scheme = match self.base.scheme() {
+            "https" | "wss" => "wss",
+            _ => "ws",
+        };
+        self.url(scheme, path, query)
+    }
+
+    /// `http://`/`https://` URL of `path` under the server URL.
+    pub fn http_url(&self, path: &[&str], query: &[(&str, &str)]) -> Url {
+        let scheme = match self.base.scheme() {
+            "https" | "wss" => "https",
+            _ => "http",
+        };
+        self.url(scheme, path, query)
+    }
+
+    fn url(&self, scheme: &str, path: &[&str], query: &[(&str, &str)]) -> Url {
+        let mut url = self.base.clone();
+        // ws/wss and http/https are all special schemes, so this cannot fail.
+        let _ = url.set_scheme(scheme);
+        url.path_segments_mut()
+            .expect("checked scheme has a path")
+            .pop_if_empty()
+            .extend(path);
+        url.set_query(None);
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        url
+    }
+
+    pub async fn connect(&self, url: &Url) -> Result<WsStream> {
+        let mut url = url.clone();
+        if let Some((name, token)) = &self.token_query {
+            url.query_pairs_mut().append_pair(name, token);
+        }
+        let mut request = url.as_str().into_client_request()?;
+        request.headers_mut().extend(self.headers.clone());
+        let connector = self.tls.clone().map(Connector::Rustls);
+        let (stream, _) = match proxy::for_target(self.proxy.as_ref(), &url)? {
+            Some(proxy) => {
+                let host = url.host_str().unwrap_or_default();
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                let port = url.port_or_known_default().unwrap_or(80);
+                tracing::debug!(
+                    "Tunneling to {}:{} through {} proxy {}",
+                    host,
+                    port,
+                    proxy.scheme(),
+                    proxy.host_str().unwrap_or_default()
+                );
+                let tunnel = proxy::connect(&proxy, host, port).await?;
+                tokio_tungstenite::client_async_tls_with_config(request, tunnel, None, connector)
+                    .await?
+            }
+            None => {
+                tokio_tungstenite::connect_async_tls_with_config(request, None, false, connector)
+                    .await?
+            }
+        };
+        Ok(stream)
+    }
+
+    pub fn get(&self, url: Url) -> reqwest::RequestBuilder {
+        self.request(reqwest::Method::GET, url)
+    }
+
+    pub fn post(&self, url: Url) -> reqwest::RequestBuilder {
+        self.request(reqwest::Method::POST, url)
+    }
+
+    fn request(&self, method: reqwest::Method, url: Url) -> reqwest::RequestBuilder {
+        let request = self.http.request(method, url);
+        match &self.token_query {
+            Some((name, token)) => request.query(&[(name, token)]),
+            None => request,
+        }
+    }
+}
+
+/// Certificates and keys named by the TLS flags.
+struct TlsFiles {
+    roots: Vec<CertificateDer<'static>>,
+    identity: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    /// The same identity as one PEM blob, the form reqwest takes.
+    identity_pem: Option<Vec<u8>>,
+}
+
+impl TlsFiles {
+    fn load(cli: &Cli) -> Result<Self> {
+        let roots = match &cli.ca_cert {
+            Some(path) => {
+                let certs = read_certs(path)?;
+                if certs.is_empty() {
+                    bail!("No certificates in {}", path.display());
+                }
+                certs
+            }
+            None => Vec::new(),
+        };
+        let (identity, identity_pem) = match (&cli.client_cert, &cli.client_key) {
+            (Some(cert_path), Some(key_path)) => {
+                let certs = read_certs(cert_path)?;
+                if certs.is_empty() {
+                    bail!("No certificates in {}", cert_path.display());
+                }
+                let key = PrivateKeyDer::from_pem_file(key_path)
+                    .with_context(|| format!("No private key in {}", key_path.display()))?;
+                let mut pem = std::fs::read(cert_path)?;
+                pem.push(b'\n');
+                pem.extend(std::fs::read(key_path)?);
+                (Some((certs, key)), Some(pem))
+            }
+            _ => (None, None),
+        };
+        Ok(Self {
+            roots,
+            identity,
+            identity_pem,
+        })
+    }
+
+    fn is_default(&self) -> bool {
+        self.roots.is_empty() && self.identity.is_none()
+    }
+
+    fn rustls_config(self, insecure: bool) -> Result<rustls::ClientConfig> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .context("Failed to set up TLS")?;
+        let builder = if insecure {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(AcceptAnyCert(
+                    provider.signature_verification_algorithms,
+                )))
+        } else {
+            let mut roots = rustls::RootCertStore::empty();
+            let native = rustls_native_certs::load_native_certs();
+            for e in &native.errors {
+                tracing::debug!("Skipping system certificate: {}", e);
+            }
+            roots.add_parsable_certificates(native.certs);
+            for cert in self.roots {
+                roots.add(cert).context("Invalid --ca-cert")?;
+            }
+            builder.with_root_certificates(roots)
+        };
+        match self.identity {
+            Some((certs, key)) => builder
+                .with_client_auth_cert(certs, key)
+                .context("Invalid --client-cert/--client-key"),
+            None => Ok(builder.with_no_client_auth()),
+        }
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_file_iter(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("Invalid certificate in {}", path.display()))
+}
+
+/// `--insecure`: any certificate is accepted, but handshake signatures are
+/// still checked so the session itself is sound.
+#[derive(Debug)]
+struct AcceptAnyCert(WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn client(args: &[&str]) -> ServerClient {
+        let cli = Cli::parse_from(
+            ["notir-cli"]
+                .iter()
+                .chain(args)
+                .chain(&["sub", "--id", "x"]),
+        );
+        ServerClient::new(&cli).unwrap()
+    }
+
+    #[test]
+    fn urls_map_schemes_and_encode_ids() {
+        let plain = client(&["--server", "ws://localhost:5800/"]);
+        assert_eq!(
+            plain
+                .ws_url(&["single", "sub"], &[("id", "a b/c&d")])
+                .as_str(),
+            "ws://localhost:5800/single/sub?id=a+b%2Fc%26d"
+        );
+        assert_eq!(
+            plain.http_url(&["files", "download", "f/1"], &[]).as_str(),
+            "http://localhost:5800/files/download/f%2F1"
+        );
+
+        let proxied = client(&["--server", "wss://notir.example.com/notir"]);
+        assert_eq!(
+            proxied.http_url(&["single", "pub"], &[]).as_str(),
+            "https://notir.example.com/notir/single/pub"
+        );
+        assert_eq!(
+            proxied.ws_url(&["broad", "sub"], &[]).as_str(),
+            "wss://notir.example.com/notir/broad/sub"
+        );
+    }
+
+    #[test]
+    fn token_goes_to_a_header_or_the_query() {
+        let header = client(&["--token", "s3cret", "--header", "X-Team: ops"]);
+        assert_eq!(header.headers[AUTHORIZATION], "Bearer s3cret");
+        assert_eq!(header.headers["x-team"], "ops");
+        let request = header.get(header.http_url(&["files", "list"], &[])).build();
+        assert_eq!(request.unwrap().url().query(), None);
+
+        let query = client(&["--token", "s3cret", "--token-query", "access_token"]);
+        assert!(!query.headers.contains_key(AUTHORIZATION));
+        let request = query
+            .get(query.http_url(&["files", "list"], &[("room", "r1")]))
+            .build()
+            .unwrap();
+        assert_eq!(request.url().query(), Some("room=r1&access_token=s3cret"));
+    }
+}
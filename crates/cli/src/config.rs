@@ -0,0 +1,314 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::args::{FileMode, OutputFormat, OutputMode, SubArgs, SubscriptionMode};
+
+/// One channel to follow, with the options it runs with.
+#[derive(Debug)]
+pub struct Subscription {
+    pub id: String,
+    pub mode: SubscriptionMode,
+    pub args: SubArgs,
+}
+
+/// A `--config` file: `[[subscription]]` tables.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    subscription: Vec<ConfigEntry>,
+}
+
+/// Settings of one subscription in a config file. Unset fields fall back to
+/// the command line.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigEntry {
+    id: String,
+    #[serde(default = "default_mode")]
+    mode: SubscriptionMode,
+    script: Option<String>,
+    state_file: Option<PathBuf>,
+    output: Option<OutputMode>,
+    output_dir: Option<String>,
+    format: Option<OutputFormat>,
+    file_name: Option<String>,
+    webhook_url: Option<String>,
+    webhook_spool: Option<PathBuf>,
+    exec: Option<String>,
+}
+
+fn default_mode() -> SubscriptionMode {
+    SubscriptionMode::Single
+}
+
+/// Collects the subscriptions from `--id`, `--sub` and `--config`, in that order.
+pub fn subscriptions(cli: &SubArgs) -> Result<Vec<Subscription>> {
+    let mut subs = Vec::new();
+    let channel = |id: &str, mode| Subscription {
+        id: id.to_string(),
+        mode,
+        args: cli.clone(),
+    };
+    if let Some(id) = &cli.id {
+        subs.push(channel(id, cli.mode));
+    }
+    for (mode, id) in &cli.subs {
+        subs.push(channel(id, *mode));
+    }
+    if let Some(path) = &cli.config {
+        for entry in read_config(path)?.subscription {
+            let mut sub = channel(&entry.id, entry.mode);
+            entry.apply(&mut sub.args);
+            subs.push(sub);
+        }
+    }
+    validate(&subs)?;
+    Ok(subs)
+}
+
+/// The only subscription, for commands that handle one channel.
+pub fn single(cli: &SubArgs) -> Result<Subscription> {
+    let mut subs = subscriptions(cli)?;
+    if subs.len() != 1 {
+        bail!("expected one channel (--id), got {}", subs.len());
+    }
+    Ok(subs.remove(0))
+}
+
+fn read_config(path: &Path) -> Result<ConfigFile> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let config: ConfigFile =
+        toml::from_str(&text).with_context(|| format!("Invalid config {}", path.display()))?;
+    if config.subscription.is_empty() {
+        bail!("{} has no [[subscription]] entries", path.display());
+    }
+    Ok(config)
+}
+
+impl ConfigEntry {
+    fn apply(self, args: &mut SubArgs) {
+        args.script = self.script.or(args.script.take());
+        args.state_file = self.state_file.or(args.state_file.take());
+        args.output = self.output.unwrap_or(args.output);
+        args.output_dir = self
+            .output_dir
+            .unwrap_or(std::mem::take(&mut args.output_dir));
+        args.format = self.format.unwrap_or(args.format);
+        args.file_name = self.file_name.or(args.file_name.take());
+        args.webhook_url = self.webhook_url.or(args.webhook_url.take());
+        args.webhook_spool = self.webhook_spool.or(args.webhook_spool.take());
+        args.exec = self.exec.or(args.exec.take());
+    }
+}
+
+impl Subscription {
+    /// `--file-name`, or the default for the file mode.
+    pub fn file_template(&self) -> String {
+        self.args.file_name.clone().unwrap_or_else(|| {
+            match self.args.file_mode {
+                FileMode::Append => "{id}.txt",
+                FileMode::Individual => "{seq}.{ext}",
+            }
+            .to_string()
+        })
+    }
+
+    /// Where `--output webhook` spools: `--webhook-spool` or `<output-dir>/<id>.spool`.
+    pub fn webhook_spool(&self) -> PathBuf {
+        self.args
+            .webhook_spool
+            .clone()
+            .unwrap_or_else(|| Path::new(&self.args.output_dir).join(format!("{}.spool", self.id)))
+    }
+
+    /// Where the `--route-webhook` target `name` spools.
+    pub fn route_spool(&self, name: &str) -> PathBuf {
+        Path::new(&self.args.output_dir).join(format!("{}.{}.spool", self.id, name))
+    }
+
+    /// Where the script's `file:NAME` targets write. Names are only known at
+    /// run time, so `*` stands for all of them.
+    fn route_files(&self) -> PathBuf {
+        Path::new(&self.args.output_dir).join(format!("{}.*.txt", self.id))
+    }
+
+    /// Files and directories only this subscription may write: its output,
+    /// routed files, spools and state. Dated file names are compared as
+    /// templates.
+    fn owned_paths(&self) -> Vec<PathBuf> {
+        let args = &self.args;
+        let dir = Path::new(&args.output_dir);
+        let mut paths: Vec<PathBuf> = args.state_file.iter().cloned().collect();
+        if matches!(args.output, OutputMode::File | OutputMode::Both) {
+            paths.push(match args.file_mode {
+                FileMode::Append => dir.join(self.file_template().replace("{id}", &self.id)),
+                FileMode::Individual => dir.join(&self.id),
+            });
+        }
+        if args.script.is_some() {
+            paths.push(self.route_files());
+        }
+        if args.webhook_url.is_some() {
+            paths.push(self.webhook_spool());
+        }
+        for (name, _) in &args.route_webhook {
+            paths.push(self.route_spool(name));
+        }
+        paths
+    }
+}
+
+/// A channel may be followed once per mode. The same id in both modes is
+/// fine as long as the two do not write the same files, which are named
+/// after the id unless set per subscription.
+fn validate(subs: &[Subscription]) -> Result<()> {
+    let mut channels = HashSet::new();
+    let mut owners: HashMap<PathBuf, String> = HashMap::new();
+    for sub in subs {
+        let channel = format!("{}:{}", sub.mode, sub.id);
+        if !channels.insert(channel.clone()) {
+            bail!("channel {} is subscribed more than once", channel);
+        }
+        for path in sub.owned_paths() {
+            if let Some(other) = owners.get(&path) {
+                bail!(
+                    "{} and {} would both write {}; set output_dir, file_name, webhook_spool \
+                     or state_file per subscription in --config",
+                    other,
+                    channel,
+                    path.display()
+                );
+            }
+            owners.insert(path, channel.clone());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{Cli, Command};
+    use clap::Parser;
+
+    fn sub_args(args: &[&str]) -> SubArgs {
+        let cli = Cli::parse_from(["notir-cli", "sub"].iter().chain(args));
+        let Command::Sub(args) = cli.command else {
+            panic!("expected sub");
+        };
+        *args
+    }
+
+    #[test]
+    fn config_entries_override_the_command_line() {
+        let path = std::env::temp_dir().join("notir_test_config.toml");
+        std::fs::write(
+            &path,
+            r#"
+[[subscription]]
+id = "alerts"
+script = "alerts.js"
+output = "file"
+
+[[subscription]]
+id = "room1"
+mode = "broad"
+"#,
+        )
+        .unwrap();
+        let config = path.to_str().unwrap();
+        let subs = subscriptions(&sub_args(&[
+            "--id",
+            "abc",
+            "--sub",
+            "broad:lobby",
+            "--config",
+            config,
+            "--format",
+            "jsonl",
+        ]))
+        .unwrap();
+        let channels: Vec<_> = subs.iter().map(|s| (s.mode, s.id.as_str())).collect();
+        assert_eq!(
+            channels,
+            vec![
+                (SubscriptionMode::Single, "abc"),
+                (SubscriptionMode::Broad, "lobby"),
+                (SubscriptionMode::Single, "alerts"),
+                (SubscriptionMode::Broad, "room1"),
+            ]
+        );
+        assert_eq!(subs[2].args.script.as_deref(), Some("alerts.js"));
+        assert_eq!(subs[2].args.output, OutputMode::File);
+        assert_eq!(subs[2].args.format, OutputFormat::Jsonl);
+        assert_eq!(subs[3].args.script, None);
+
+        std::fs::write(&path, "[[subscription]]\nid = \"x\"\nscrpit = \"x.js\"\n").unwrap();
+        assert!(subscriptions(&sub_args(&["--config", config])).is_err());
+    }
+
+    #[test]
+    fn shared_ids_and_state_files_are_rejected() {
+        assert!(subscriptions(&sub_args(&["--id", "abc", "--sub", "single:abc"])).is_err());
+        // One id in both modes is two channels; only shared files conflict.
+        assert_eq!(
+            subscriptions(&sub_args(&["--id", "abc", "--sub", "broad:abc"]))
+                .unwrap()
+                .len(),
+            2
+        );
+        let err = subscriptions(&sub_args(&[
+            "--id",
+            "abc",
+            "--sub",
+            "broad:abc",
+            "--output",
+            "file",
+        ]))
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("single:abc and broad:abc would both write")
+        );
+        assert!(
+            subscriptions(&sub_args(&[
+                "--id",
+                "abc",
+                "--sub",
+                "broad:room1",
+                "--state-file",
+                "state.json"
+            ]))
+            .is_err()
+        );
+        let err = subscriptions(&sub_args(&[
+            "--id",
+            "abc",
+            "--sub",
+            "broad:abc",
+            "--script",
+            "route.js",
+        ]))
+        .unwrap_err();
+        assert!(err.to_string().contains("abc.*.txt"));
+        assert!(
+            subscriptions(&sub_args(&[
+                "--id",
+                "abc",
+                "--sub",
+                "broad:room1",
+                "--script",
+                "route.js"
+            ]))
+            .is_ok()
+        );
+        assert_eq!(
+            single(&sub_args(&["--sub", "broad:room1"])).unwrap().id,
+            "room1"
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::{Context, Result, bail};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::{Semaphore, mpsc};
+use tokio::task::JoinHandle;
+
+use crate::script_api::WsEvent;
+
+/// Event fields exported to the command as `NOTIR_*` environment variables.
+#[derive(Debug, Clone)]
+pub struct ExecEnv {
+    pub id: String,
+    pub msg_type: &'static str,
+    pub timestamp: String,
+}
+
+impl ExecEnv {
+    pub fn new(id: &str, event: &WsEvent) -> Self {
+        Self {
+            id: id.to_string(),
+            msg_type: event.msg_type,
+            timestamp: event.timestamp.clone(),
+        }
+    }
+}
+
+struct Job {
+    payload: String,
+    env: ExecEnv,
+}
+
+/// Runs a shell command for every transformed message.
+///
+/// Fire-and-forget jobs go through a bounded queue and run up to
+/// `concurrency` at a time; messages are dropped when the queue is full.
+pub struct ExecHook {
+    command: Arc<String>,
+    timeout: Duration,
+    concurrency: usize,
+    permits: Arc<Semaphore>,
+    queue: mpsc::Sender<Job>,
+    dispatcher: JoinHandle<()>,
+}
+
+impl ExecHook {
+    pub fn new(command: &str, concurrency: usize, timeout: Duration, max_queue: usize) -> Self {
+        let command = Arc::new(command.to_string());
+        let concurrency = concurrency.max(1);
+        let permits = Arc::new(Semaphore::new(concurrency));
+        let (queue, mut rx) = mpsc::channel::<Job>(max_queue.max(1));
+
+        let dispatcher = {
+            let command = command.clone();
+            let permits = permits.clone();
+            tokio::spawn(async move {
+                while let Some(job) = rx.recv().await {
+                    let Ok(permit) = permits.clone().acquire_owned().await else {
+                        break;
+                    };
+                    let command = command.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) =
+                            run_command(&command, &job.payload, &job.env, timeout, false).await
+                        {
+                            tracing::warn!("Exec hook failed: {:#}", e);
+                        }
+                        drop(permit);
+                    });
+                }
+            })
+        };
+
+        Self {
+            command,
+            timeout,
+            concurrency,
+            permits,
+            queue,
+            dispatcher,
+        }
+    }
+
+    /// Queues the command for `payload` without waiting for it.
+    pub fn submit(&self, payload: &str, env: ExecEnv) {
+        let job = Job {
+            payload: payload.to_string(),
+            env,
+        };
+        if self.queue.try_send(job).is_err() {
+            tracing::warn!("Exec queue full, dropping message");
+        }
+    }
+
+    /// Runs the command for `payload` and returns its stdout, for use as a
+    /// ping_pong reply. One trailing newline is stripped.
+    pub async fn run_for_reply(&self, payload: &str, env: &ExecEnv) -> Result<String> {
+        let _permit = self.permits.acquire().await?;
+        let stdout = run_command(&self.command, payload, env, self.timeout, true).await?;
+        let mut reply = String::from_utf8_lossy(&stdout).into_owned();
+        if reply.ends_with('\n') {
+            reply.pop();
+            if reply.ends_with('\r') {
+                reply.pop();
+            }
+        }
+        Ok(reply)
+    }
+
+    /// Waits for queued and running commands to finish.
+    pub async fn finish(self) {
+        drop(self.queue);
+        let _ = self.dispatcher.await;
+        let _ = self.permits.acquire_many(self.concurrency as u32).await;
+    }
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+}
+
+/// Runs `command` with `payload` on stdin. Stdout is captured when
+/// `capture` is set and otherwise sent to our stderr, so it cannot mix with
+/// the message output; stderr is always inherited.
+async fn run_command(
+    command: &str,
+    payload: &str,
+    env: &ExecEnv,
+    timeout: Duration,
+    capture: bool,
+) -> Result<Vec<u8>> {
+    let mut child = shell(command)
+        .env("NOTIR_ID", &env.id)
+        .env("NOTIR_TYPE", env.msg_type)
+        .env("NOTIR_TIMESTAMP", &env.timestamp)
+        .stdin(Stdio::piped())
+        .stdout(if capture {
+            Stdio::piped()
+        } else {
+            Stdio::from(std::io::stderr())
+        })
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to spawn '{}'", command))?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let payload = payload.as_bytes().to_vec();
+    let feed = async move {
+        // A command that ignores stdin closes the pipe early; that is fine.
+        let _ = stdin.write_all(&payload).await;
+    };
+
+    let output = match tokio::time::timeout(timeout, async {
+        let (_, output) = tokio::join!(feed, child.wait_with_output());
+        output
+    })
+    .await
+    {
+        Ok(output) => output.context("Failed to wait for command")?,
+        Err(_) => bail!("'{}' timed out after {:?}", command, timeout),
+    };
+
+    if !output.status.success() {
+        bail!("'{}' exited with {}", command, output.status);
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn env() -> ExecEnv {
+        ExecEnv {
+            id: "worker".to_string(),
+            msg_type: "text",
+            timestamp: "2026-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn reply_is_command_stdout_with_env() {
+        let hook = ExecHook::new(
+            "printf '%s|%s|' \"$NOTIR_ID\" \"$NOTIR_TYPE\"; cat; echo",
+            1,
+            Duration::from_secs(5),
+            4,
+        );
+        let reply = hook.run_for_reply("payload", &env()).await.unwrap();
+        assert_eq!(reply, "worker|text|payload");
+        hook.finish().await;
+    }
+
+    #[tokio::test]
+    async fn failures_and_timeouts_are_errors() {
+        let hook = ExecHook::new("exit 2", 1, Duration::from_secs(5), 4);
+        assert!(hook.run_for_reply("", &env()).await.is_err());
+
+        let hook = ExecHook::new("sleep 5", 1, Duration::from_millis(100), 4);
+        assert!(hook.run_for_reply("", &env()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn finish_waits_for_queued_commands() {
+        let out = std::env::temp_dir().join("notir_test_exec.txt");
+        let _ = std::fs::remove_file(&out);
+        let hook = ExecHook::new(
+            &format!("cat >> {}", out.display()),
+            1,
+            Duration::from_secs(5),
+            8,
+        );
+        hook.submit("a", env());
+        hook.submit("b", env());
+        hook.finish().await;
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "ab");
+    }
+}
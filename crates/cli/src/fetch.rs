@@ -0,0 +1,426 @@
+use anyhow::{Context, Result, bail};
+use futures_util::StreamExt;
+use reqwest::{StatusCode, header};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::args::FetchArgs;
+use crate::client::ServerClient;
+use crate::util::glob_match;
+
+/// Header carrying the password of a protected offer.
+const PASSWORD_HEADER: &str = "x-notir-password";
+/// A holder streams one file at a time; busy holders answer 409.
+const BUSY_RETRIES: u32 = 10;
+const BUSY_RETRY_DELAY: Duration = Duration::from_secs(2);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// File offer metadata as returned by `/files/list` and `offer_added`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OfferInfo {
+    pub file_id: String,
+    pub name: String,
+    pub size: u64,
+    #[serde(default)]
+    pub protected: bool,
+    #[serde(default)]
+    pub members: Option<Vec<Value>>,
+}
+
+#[derive(Deserialize)]
+struct OfferList {
+    files: Vec<OfferInfo>,
+}
+
+pub async fn run(server: &ServerClient, args: &FetchArgs) -> Result<()> {
+    tokio::fs::create_dir_all(&args.output_dir)
+        .await
+        .with_context(|| format!("Failed to create {}", args.output_dir.display()))?;
+
+    // Subscribe before listing, so offers added in between are not missed.
+    let added = if args.watch {
+        Some(follow(server, args).await?)
+    } else {
+        None
+    };
+    let offers = list_offers(server, &args.room).await?;
+
+    if args.files.is_empty() && !args.all && !args.watch {
+        print_offers(&offers);
+        return Ok(());
+    }
+
+    let mut wanted = Vec::new();
+    for selector in &args.files {
+        match offers
+            .iter()
+            .find(|o| &o.file_id == selector || &o.name == selector)
+        {
+            Some(offer) => wanted.push(offer),
+            None => bail!("No offer matching '{}' in room {}", selector, args.room),
+        }
+    }
+    if args.all {
+        wanted.extend(offers.iter().filter(|o| glob_match(&args.pattern, &o.name)));
+    }
+
+    let mut fetched = HashSet::new();
+    let mut failed = 0;
+    for offer in wanted {
+        if !fetched.insert(offer.file_id.clone()) {
+            continue;
+        }
+        if let Err(e) = download(server, offer, args).await {
+            tracing::error!("Failed to download {}: {:#}", offer.name, e);
+            failed += 1;
+        }
+    }
+
+    if let Some(mut added) = added {
+        while let Some(offer) = added.recv().await {
+            // Offers added while the list was taken show up in both.
+            if !fetched.insert(offer.file_id.clone()) {
+                continue;
+            }
+            if let Err(e) = download(server, &offer, args).await {
+                tracing::error!("Failed to download {}: {:#}", offer.name, e);
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} download(s) failed", failed);
+    }
+    Ok(())
+}
+
+async fn list_offers(server: &ServerClient, room: &str) -> Result<Vec<OfferInfo>> {
+    let resp = server
+        .get(server.http_url(&["files", "list"], &[("room", room)]))
+        .send()
+        .await
+        .context("Failed to list offers")?
+        .error_for_status()
+        .context("Failed to list offers")?;
+    let list: OfferList = resp.json().await.context("Invalid offer list")?;
+    Ok(list.files)
+}
+
+fn print_offers(offers: &[OfferInfo]) {
+    if offers.is_empty() {
+        eprintln!("No offers in this room");
+        return;
+    }
+    for offer in offers {
+        let mut flags = Vec::new();
+        if offer.members.is_some() {
+            flags.push("bundle");
+        }
+        if offer.protected {
+            flags.push("protected");
+        }
+        println!(
+            "{}\t{}\t{}\t{}",
+            offer.file_id,
+            format_size(offer.size),
+            offer.name,
+            flags.join(",")
+        );
+    }
+}
+
+/// Follows the room and queues every new offer matching `--pattern`. The
+/// socket is read on its own task so it keeps answering pings while the
+/// queued offers download; the queue closes when the connection does.
+async fn follow(
+    server: &ServerClient,
+    args: &FetchArgs,
+) -> Result<mpsc::UnboundedReceiver<OfferInfo>> {
+    let url = server.ws_url(&["broad", "sub"], &[("id", &args.room)]);
+    tracing::info!("Watching {} for new offers", url);
+    let mut ws_stream = server
+        .connect(&url)
+        .await
+        .with_context(|| format!("Failed to connect to {}", url))?;
+
+    let (tx, rx) = mpsc::unbounded_channel::<OfferInfo>();
+    let pattern = args.pattern.clone();
+    tokio::spawn(async move {
+        while let Some(msg) = ws_stream.next().await {
+            match msg {
+                Ok(Message::Binary(data)) => {
+                    let Ok(control) = serde_json::from_slice::<Value>(&data) else {
+                        continue;
+                    };
+                    if control["op"] != "offer_added" {
+                        continue;
+                    }
+                    let Ok(offer) = serde_json::from_value::<OfferInfo>(control["file"].clone())
+                    else {
+                        continue;
+                    };
+                    if glob_match(&pattern, &offer.name) {
+                        tracing::info!("New offer {} ({})", offer.name, offer.file_id);
+                        let _ = tx.send(offer);
+                    } else {
+                        tracing::debug!("Skipping offer {} (pattern mismatch)", offer.name);
+                    }
+                }
+                Ok(Message::Close(frame)) => {
+                    tracing::info!("Server closed connection: {:?}", frame);
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!("WebSocket error: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+    Ok(rx)
+}
+
+/// Downloads one offer into the output directory via a `.part` file,
+/// resuming it with a `Range` request when the server honours ranges.
+async fn download(server: &ServerClient, offer: &OfferInfo, args: &FetchArgs) -> Result<PathBuf> {
+    let file_name = free_file_name(&args.output_dir, &local_file_name(&offer.name)).await;
+    let target = args.output_dir.join(&file_name);
+    let part = args.output_dir.join(format!("{}.part", file_name));
+
+    let url = server.http_url(&["files", "download", &offer.file_id], &[]);
+    let mut attempts = 0;
+    let resp = loop {
+        let offset = file_len(&part).await;
+        let mut req = server.get(url.clone());
+        if offset > 0 {
+            req = req.header(header::RANGE, format!("bytes={}-", offset));
+        }
+        if let Some(password) = &args.password {
+            req = req.header(PASSWORD_HEADER, password);
+        }
+        let resp = req.send().await.context("Request failed")?;
+        if resp.status() == StatusCode::CONFLICT && attempts < BUSY_RETRIES {
+            attempts += 1;
+            tracing::info!(
+                "Holder busy, retrying {} in {:?}",
+                offer.name,
+                BUSY_RETRY_DELAY
+            );
+            tokio::time::sleep(BUSY_RETRY_DELAY).await;
+            continue;
+        }
+        break resp;
+    };
+
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        let message = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|v| v["error"].as_str().map(str::to_string))
+            .unwrap_or(body);
+        bail!("server returned {}: {}", status, message);
+    }
+
+    let resumed = status == StatusCode::PARTIAL_CONTENT;
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&part)
+        .await
+        .with_context(|| format!("Failed to open {}", part.display()))?;
+    let mut received = if resumed { file_len(&part).await } else { 0 };
+    if resumed {
+        tracing::info!("Resuming {} at {}", offer.name, format_size(received));
+    }
+
+    let total = if offer.members.is_some() {
+        None
+    } else {
+        Some(offer.size)
+    };
+    let mut progress = Progress::new(&offer.name, total);
+    let mut resp = resp;
+    while let Some(chunk) = resp.chunk().await.context("Download interrupted")? {
+        file.write_all(&chunk).await?;
+        received += chunk.len() as u64;
+        progress.update(received);
+    }
+    file.flush().await?;
+    drop(file);
+    progress.finish(received);
+
+    tokio::fs::rename(&part, &target)
+        .await
+        .with_context(|| format!("Failed to move download to {}", target.display()))?;
+    println!("{}", target.display());
+    Ok(target)
+}
+
+async fn file_len(path: &Path) -> u64 {
+    tokio::fs::metadata(path)
+        .await
+        .map(|m| m.len())
+        .unwrap_or(0)
+}
+
+/// Last path segment of an offer name, safe to use inside the output directory.
+fn local_file_name(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    if name.is_empty() || name == "." || name == ".." {
+        "download".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// `name`, or `name-1`, `name-2`, ... (before the extension) if a file by
+/// that name is already in `dir`, so offers with the same base name from
+/// different folders do not overwrite each other.
+async fn free_file_name(dir: &Path, name: &str) -> String {
+    let (stem, ext) = match name.char_indices().skip(1).find(|(_, c)| *c == '.') {
+        Some((i, _)) => name.split_at(i),
+        None => (name, ""),
+    };
+    let mut candidate = name.to_string();
+    let mut n = 0;
+    while tokio::fs::try_exists(dir.join(&candidate))
+        .await
+        .unwrap_or(false)
+    {
+        n += 1;
+        candidate = format!("{}-{}{}", stem, n, ext);
+    }
+    candidate
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Single-line progress on stderr when it is a terminal.
+struct Progress {
+    name: String,
+    total: Option<u64>,
+    started: Instant,
+    last: Instant,
+    enabled: bool,
+}
+
+impl Progress {
+    fn new(name: &str, total: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self {
+            name: name.to_string(),
+            total,
+            started: now,
+            last: now,
+            enabled: std::io::stderr().is_terminal(),
+        }
+    }
+
+    fn update(&mut self, received: u64) {
+        if !self.enabled || self.last.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        self.last = Instant::now();
+        self.render(received);
+    }
+
+    fn finish(&mut self, received: u64) {
+        if self.enabled {
+            self.render(received);
+            eprintln!();
+        }
+        tracing::info!(
+            "Downloaded {} ({}) in {:.1}s",
+            self.name,
+            format_size(received),
+            self.started.elapsed().as_secs_f64()
+        );
+    }
+
+    fn render(&self, received: u64) {
+        let line = match self.total {
+            Some(total) if total > 0 => format!(
+                "{}  {} / {} ({}%)",
+                self.name,
+                format_size(received),
+                format_size(total),
+                received.saturating_mul(100) / total
+            ),
+            _ => format!("{}  {}", self.name, format_size(received)),
+        };
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\r\x1b[2K{}", line);
+        let _ = stderr.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_file_name_strips_directories() {
+        assert_eq!(local_file_name("dist/app.tar"), "app.tar");
+        assert_eq!(local_file_name("..\\evil.exe"), "evil.exe");
+        assert_eq!(local_file_name(".."), "download");
+    }
+
+    #[tokio::test]
+    async fn free_file_name_numbers_taken_names() {
+        let dir = std::env::temp_dir().join("notir_test_fetch_names");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(free_file_name(&dir, "report.txt").await, "report.txt");
+        std::fs::write(dir.join("report.txt"), "a").unwrap();
+        std::fs::write(dir.join("app.tar.gz"), "a").unwrap();
+        std::fs::write(dir.join("app-1.tar.gz"), "a").unwrap();
+        std::fs::write(dir.join(".env"), "a").unwrap();
+        assert_eq!(free_file_name(&dir, "report.txt").await, "report-1.txt");
+        assert_eq!(free_file_name(&dir, "app.tar.gz").await, "app-2.tar.gz");
+        assert_eq!(free_file_name(&dir, ".env").await, ".env-1");
+    }
+
+    #[test]
+    fn format_size_uses_binary_units() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[test]
+    fn offer_info_parses_list_entries() {
+        let list: OfferList = serde_json::from_str(
+            r#"{"files":[{"fileId":"f1","name":"a.txt","size":3,"mime":"text/plain","connId":1,"createdAt":1,"protected":true},
+                         {"fileId":"f2","name":"b.zip","size":9,"members":[{"name":"x","size":9}]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(list.files.len(), 2);
+        assert!(list.files[0].protected);
+        assert!(list.files[1].members.is_some());
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::util::glob_match;
+
+/// Largest response body handed to a script.
+const MAX_BODY: usize = 8 * 1024 * 1024;
+
+/// A request queued by the script's `fetch()`.
+#[derive(Debug, Deserialize)]
+pub struct FetchRequest {
+    pub id: u64,
+    pub url: String,
+    pub method: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<String>,
+}
+
+pub struct FetchResponse {
+    pub status: u16,
+    /// Lower-cased header names to values, as a JSON object.
+    pub headers: String,
+    pub body: String,
+}
+
+/// Performs `fetch()` calls for scripts, limited to an allowlist of hosts.
+/// Without an allowlist every request is refused.
+#[derive(Clone)]
+pub struct FetchClient {
+    client: reqwest::Client,
+    allow: Arc<Vec<String>>,
+}
+
+impl FetchClient {
+    pub fn new(
+        allow: Vec<String>,
+        timeout: Duration,
+        proxy: Option<reqwest::Proxy>,
+    ) -> Result<Self> {
+        let allow = Arc::new(allow);
+        let redirect_allow = allow.clone();
+        let mut client = reqwest::Client::builder().timeout(timeout).redirect(
+            reqwest::redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= 10 {
+                    attempt.error("too many redirects")
+                } else if is_allowed(&redirect_allow, attempt.url()) {
+                    attempt.follow()
+                } else {
+                    attempt.error("redirect to a host that is not allowed")
+                }
+            }),
+        );
+        if let Some(proxy) = proxy {
+            client = client.proxy(proxy);
+        }
+        let client = client
+            .build()
+            .context("Failed to build script fetch client")?;
+        Ok(Self { client, allow })
+    }
+
+    /// Checks the request against the allowlist before anything is sent.
+    pub fn check(&self, request: &FetchRequest) -> Result<reqwest::Url, String> {
+        let url = reqwest::Url::parse(&request.url).map_err(|e| format!("invalid URL: {}", e))?;
+        if !is_allowed(&self.allow, &url) {
+            return Err(format!(
+                "host '{}' is not allowed (see --fetch-allow)",
+                url.host_str().unwrap_or_default()
+            ));
+        }
+        Ok(url)
+    }
+
+    pub async fn send(
+        &self,
+        request: FetchRequest,
+        url: reqwest::Url,
+    ) -> Result<FetchResponse, String> {
+        let method = reqwest::Method::from_bytes(request.method.as_bytes())
+            .map_err(|_| format!("invalid method '{}'", request.method))?;
+        let mut builder = self.client.request(method, url);
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = request.body {
+            builder = builder.body(body);
+        }
+        let mut resp = builder.send().await.map_err(|e| e.to_string())?;
+
+        let status = resp.status().as_u16();
+        let headers: BTreeMap<String, String> = resp
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let mut body = Vec::new();
+        while let Some(chunk) = resp.chunk().await.map_err(|e| e.to_string())? {
+            if body.len() + chunk.len() > MAX_BODY {
+                return Err(format!("response body exceeds {} bytes", MAX_BODY));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(FetchResponse {
+            status,
+            headers: serde_json::to_string(&headers).map_err(|e| e.to_string())?,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })
+    }
+}
+
+/// Matches the URL's host (or `host:port`) against the allowlist patterns.
+fn is_allowed(allow: &[String], url: &reqwest::Url) -> bool {
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let with_port = url.port().map(|port| format!("{}:{}", host, port));
+    allow.iter().any(|pattern| {
+        glob_match(pattern, host)
+            || with_port
+                .as_deref()
+                .is_some_and(|hp| glob_match(pattern, hp))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowlist_matches_hosts_and_ports() {
+        let allow = vec!["api.example.com".to_string(), "*.internal:8080".to_string()];
+        let ok = |url: &str| is_allowed(&allow, &reqwest::Url::parse(url).unwrap());
+        assert!(ok("https://api.example.com/v1/users"));
+        assert!(ok("http://api.example.com:9000/"));
+        assert!(ok("http://geo.internal:8080/lookup"));
+        assert!(!ok("http://geo.internal:8081/lookup"));
+        assert!(!ok("https://example.com/"));
+        assert!(!ok("file:///etc/passwd"));
+        assert!(!is_allowed(
+            &[],
+            &reqwest::Url::parse("https://api.example.com/").unwrap()
+        ));
+    }
+}
@@ -1,54 +1,550 @@
 use anyhow::{Context as AnyhowContext, Result};
-use rquickjs::{Context, Ctx, Function, Runtime};
+use rquickjs::loader::{Resolver, ScriptLoader};
+use rquickjs::{Context, Ctx, FromJs, Function, Module, Object, Runtime, TypedArray, Value};
+use std::cell::{Cell, RefCell};
 use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
 
-use crate::script_api::PASSTHROUGH_SCRIPT;
+use crate::js_fetch::{FetchClient, FetchRequest, FetchResponse};
+use crate::routes::Routed;
+use crate::script_api::{EVENT_LOOP_PRELUDE, PASSTHROUGH_SCRIPT, STATE_PRELUDE};
+
+/// Minimum time between two writes of the `--state-file`.
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct ScriptConfig {
+    /// Longest a single `transform`/`reply` call (including awaited timers
+    /// and fetches) may take, and the limit for evaluating the script itself.
+    pub timeout: Duration,
+    /// Host patterns `fetch()` may reach; empty disables `fetch()`.
+    pub fetch_allow: Vec<String>,
+    /// Reload the script when it changes.
+    pub watch: bool,
+    /// `--proxy` for `fetch()`; without it the proxy environment variables apply.
+    pub proxy: Option<reqwest::Proxy>,
+}
+
+impl Default for ScriptConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            fetch_allow: Vec::new(),
+            watch: false,
+            proxy: None,
+        }
+    }
+}
+
+/// Where an in-progress async call stands after running all ready JS work.
+struct Progress<T> {
+    outcome: Option<Result<T>>,
+    next_timer: Option<Duration>,
+    requests: Vec<FetchRequest>,
+}
 
 /// JS transform engine backed by rquickjs.
 ///
 /// **Not `Send`** — rquickjs Runtime is not thread-safe. Must be used from a single async task only.
 /// Do not move across tokio task boundaries.
 pub struct JsEngine {
+    script_path: Option<PathBuf>,
+    /// Replaced as a whole when a watched script changes.
+    script: RefCell<LoadedScript>,
+    watch: bool,
+    timeout: Duration,
+    /// When the running call must stop; checked by the runtime's interrupt handler.
+    deadline: Rc<Cell<Option<Instant>>>,
+    fetch: FetchClient,
+    state_file: Option<PathBuf>,
+    last_state_save: Cell<Instant>,
+}
+
+/// A script evaluated in its own runtime and context.
+struct LoadedScript {
     ctx: Context,
+    has_reply: bool,
+    /// The script and the modules it imports, as they were when loaded.
+    files: Vec<(PathBuf, FileStamp)>,
 }
 
+/// Hash of a file's contents; `None` if it could not be read. Contents, not
+/// mtime, so a quick same-length edit is not missed.
+type FileStamp = Option<u64>;
+
 impl JsEngine {
-    pub fn new(script_path: Option<&str>) -> Result<Self> {
-        let script = match script_path {
-            Some(path) => fs::read_to_string(path)
-                .with_context(|| format!("Failed to read script file: {}", path))?,
-            None => PASSTHROUGH_SCRIPT.to_string(),
+    /// Loads the script. Scripts that use `import`/`export` (or end in
+    /// `.mjs`) are evaluated as ES modules; their exported `transform` and
+    /// `reply` are used, and imports resolve relative to the importing file.
+    pub fn new(script_path: Option<&str>, config: ScriptConfig) -> Result<Self> {
+        let script_path = script_path.map(PathBuf::from);
+        let deadline = Rc::new(Cell::new(None::<Instant>));
+        let script = load_script(script_path.as_deref(), config.timeout, &deadline)?;
+        Ok(Self {
+            watch: config.watch && script_path.is_some(),
+            script_path,
+            script: RefCell::new(script),
+            timeout: config.timeout,
+            deadline,
+            fetch: FetchClient::new(config.fetch_allow, config.timeout, config.proxy)?,
+            state_file: None,
+            last_state_save: Cell::new(Instant::now()),
+        })
+    }
+
+    fn ctx(&self) -> Context {
+        self.script.borrow().ctx.clone()
+    }
+
+    /// Whether the script is reloaded when it changes.
+    pub fn watches_script(&self) -> bool {
+        self.watch
+    }
+
+    /// Reloads a watched script if it, or a module it imports, changed. The
+    /// new version replaces the running one only if it evaluates cleanly and
+    /// defines `transform`; `state` carries over. Otherwise the running
+    /// version stays and the error is logged.
+    pub fn reload_if_changed(&self) {
+        let Some(path) = self.script_path.as_deref().filter(|_| self.watch) else {
+            return;
         };
+        let changed = self
+            .script
+            .borrow()
+            .files
+            .iter()
+            .any(|(file, stamp)| file_stamp(file) != *stamp);
+        if !changed {
+            return;
+        }
 
-        let runtime = Runtime::new().with_context(|| "Failed to create JS runtime")?;
-        let ctx = Context::full(&runtime).with_context(|| "Failed to create JS context")?;
+        let reloaded = load_script(Some(path), self.timeout, &self.deadline).and_then(|script| {
+            let state = dump_state(&self.ctx())?;
+            load_state(&script.ctx, &state)?;
+            Ok(script)
+        });
+        match reloaded {
+            Ok(script) => {
+                *self.script.borrow_mut() = script;
+                tracing::info!("Reloaded script {}", path.display());
+            }
+            Err(e) => {
+                tracing::error!(
+                    "Failed to reload script {}, keeping the running version: {:#}",
+                    path.display(),
+                    e
+                );
+                // Try again only after the next edit.
+                for (file, stamp) in self.script.borrow_mut().files.iter_mut() {
+                    *stamp = file_stamp(file);
+                }
+            }
+        }
+    }
+
+    /// Restores `state` from `path` (if it exists) and saves it back there
+    /// periodically and on [`JsEngine::save_state`].
+    pub fn set_state_file(&mut self, path: &Path) -> Result<()> {
+        match fs::read_to_string(path) {
+            Ok(json) => load_state(&self.ctx(), &json)
+                .with_context(|| format!("Invalid state file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        }
+        self.state_file = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Writes `state` to the state file if the last write is old enough.
+    pub fn maybe_save_state(&self) {
+        if self.state_file.is_none() || self.last_state_save.get().elapsed() < STATE_SAVE_INTERVAL {
+            return;
+        }
+        if let Err(e) = self.save_state() {
+            tracing::warn!("Failed to save JS state: {:#}", e);
+        }
+    }
+
+    /// Writes `state` to the state file, if one is configured.
+    pub fn save_state(&self) -> Result<()> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+        let json = dump_state(&self.ctx())?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, path)?;
+        self.last_state_save.set(Instant::now());
+        Ok(())
+    }
+
+    /// Whether the script defines `reply(e)` to answer ping_pong requests.
+    pub fn has_reply(&self) -> bool {
+        self.script.borrow().has_reply
+    }
+
+    /// Calls the script's `transform(e)`. `raw` holds the payload of a
+    /// binary message and is exposed as `e.bytes` (a `Uint8Array`). An empty
+    /// result means the message was discarded.
+    pub async fn transform(&self, event_json: &str, raw: Option<&[u8]>) -> Result<Vec<Routed>> {
+        self.call("transform", event_json, raw, to_routes).await
+    }
+
+    /// Calls the script's `reply(e)`; `None` means no reply is sent.
+    pub async fn reply(&self, event_json: &str, raw: Option<&[u8]>) -> Result<Option<String>> {
+        if !self.has_reply() {
+            return Ok(None);
+        }
+        self.call("reply", event_json, raw, to_reply).await
+    }
 
+    /// Calls a script function and, if it returns a Promise, runs timers and
+    /// fetches until it settles. Anything still pending when the call ends —
+    /// including on timeout — is dropped.
+    async fn call<T>(
+        &self,
+        name: &str,
+        event_json: &str,
+        raw: Option<&[u8]>,
+        convert: Convert<T>,
+    ) -> Result<T> {
+        let ctx = self.ctx();
+        let deadline = Instant::now() + self.timeout;
+        self.deadline.set(Some(deadline));
+        let mut result = self
+            .drive(&ctx, name, event_json, raw, convert, deadline)
+            .await;
+        self.deadline.set(None);
+        if result.is_err() && Instant::now() >= deadline {
+            result = Err(anyhow::anyhow!(
+                "{}() timed out after {:?}",
+                name,
+                self.timeout
+            ));
+        }
+        ctx.with(|ctx| ctx.eval::<(), _>("__loop.reset()"))?;
+        result.with_context(|| format!("JS {} error", name))
+    }
+
+    async fn drive<T>(
+        &self,
+        ctx: &Context,
+        name: &str,
+        event_json: &str,
+        raw: Option<&[u8]>,
+        convert: Convert<T>,
+        deadline: Instant,
+    ) -> Result<T> {
         ctx.with(|ctx| {
-            inject_console(&ctx)?;
-            ctx.eval::<(), _>(script.as_str())
-                .map_err(|e| anyhow::anyhow!("JS script error: {:?}", e))?;
+            ctx.globals()
+                .get::<_, Function>(name)
+                .map_err(|_| anyhow::anyhow!("{} is not defined", name))?;
+            let event = event_value(&ctx, event_json, raw)?;
+            let start: Function = ctx.eval("__loop.start")?;
+            start
+                .call::<_, bool>((name, event))
+                .map_err(|e| anyhow::anyhow!(caught(&ctx, e)))?;
             Ok::<(), anyhow::Error>(())
-        })
-        .with_context(|| "Failed to initialize JS context")?;
+        })?;
+
+        let mut in_flight = JoinSet::new();
+        let mut settled: Vec<(u64, Result<FetchResponse, String>)> = Vec::new();
+        loop {
+            let progress = ctx.with(|ctx| {
+                let settle: Function = ctx.eval("__loop.settle")?;
+                for (id, result) in settled.drain(..) {
+                    let call = match result {
+                        Ok(resp) => settle.call::<_, ()>((
+                            id,
+                            None::<String>,
+                            resp.status,
+                            resp.headers,
+                            resp.body,
+                        )),
+                        Err(e) => settle.call::<_, ()>((id, Some(e), 0, "{}", "")),
+                    };
+                    call.map_err(|e| anyhow::anyhow!(caught(&ctx, e)))?;
+                }
+                run_ready(&ctx, convert, deadline)
+            })?;
+            if let Some(outcome) = progress.outcome {
+                return outcome;
+            }
+
+            for request in progress.requests {
+                match self.fetch.check(&request) {
+                    Ok(url) => {
+                        let fetch = self.fetch.clone();
+                        let id = request.id;
+                        in_flight.spawn(async move { (id, fetch.send(request, url).await) });
+                    }
+                    Err(e) => settled.push((request.id, Err(e))),
+                }
+            }
+            if !settled.is_empty() {
+                continue;
+            }
+            if in_flight.is_empty() && progress.next_timer.is_none() {
+                anyhow::bail!("{}() returned a Promise that can never settle", name);
+            }
 
-        Ok(Self { ctx })
+            let wake = progress
+                .next_timer
+                .map_or(deadline, |delay| (Instant::now() + delay).min(deadline));
+            tokio::select! {
+                Some(done) = in_flight.join_next(), if !in_flight.is_empty() => {
+                    settled.push(done?);
+                }
+                _ = tokio::time::sleep_until(wake.into()) => {}
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("{}() timed out after {:?}", name, self.timeout);
+            }
+        }
     }
+}
 
-    pub fn transform(&self, event_json: &str) -> Result<Option<String>> {
-        self.ctx.with(|ctx: Ctx| {
-            let func: Function = ctx
-                .eval("transform")
-                .map_err(|e| anyhow::anyhow!("JS: transform not found: {:?}", e))?;
+/// Turns the value a script function settled with into its Rust result.
+type Convert<T> = for<'js> fn(&Ctx<'js>, Value<'js>) -> Result<T>;
 
-            let json_val = ctx
-                .json_parse(event_json)
-                .map_err(|e| anyhow::anyhow!("JS JSON parse error: {:?}", e))?;
+fn to_reply<'js>(ctx: &Ctx<'js>, value: Value<'js>) -> Result<Option<String>> {
+    Option::<String>::from_js(ctx, value)
+        .map_err(|e| anyhow::anyhow!("expected a string or null: {}", e))
+}
 
-            let result: Option<String> = func
-                .call((json_val,))
-                .map_err(|e| anyhow::anyhow!("JS transform error: {:?}", e))?;
-            Ok(result)
+fn to_routes<'js>(ctx: &Ctx<'js>, value: Value<'js>) -> Result<Vec<Routed>> {
+    let routes: Function = ctx.eval("__loop.routes")?;
+    let items: Vec<Object> = routes
+        .call((value,))
+        .map_err(|e| anyhow::anyhow!(caught(ctx, e)))?;
+    items
+        .into_iter()
+        .map(|item| {
+            Ok(Routed {
+                target: item.get("target")?,
+                data: item.get("data")?,
+            })
         })
+        .collect()
+}
+
+/// Runs queued Promise jobs and due timers until neither is left, then
+/// reports the call's outcome (if known) and any new fetch requests.
+fn run_ready<T>(ctx: &Ctx, convert: Convert<T>, deadline: Instant) -> Result<Progress<T>> {
+    let loop_obj: Object = ctx.eval("__loop")?;
+    let run_timers: Function = loop_obj.get("runTimers")?;
+    loop {
+        while ctx.execute_pending_job() {
+            if Instant::now() >= deadline {
+                anyhow::bail!("timed out");
+            }
+        }
+        let fired: u32 = run_timers
+            .call(())
+            .map_err(|e| anyhow::anyhow!(caught(ctx, e)))?;
+        if fired == 0 {
+            break;
+        }
+    }
+
+    let outcome = match loop_obj.get::<_, Option<Object>>("outcome")? {
+        Some(outcome) => Some(match outcome.get::<_, Option<String>>("error")? {
+            Some(error) => Err(anyhow::anyhow!(error)),
+            None => convert(ctx, outcome.get("value")?),
+        }),
+        None => None,
+    };
+    let next_timer: f64 = loop_obj.get::<_, Function>("nextTimer")?.call(())?;
+    let requests: String = loop_obj.get::<_, Function>("takeRequests")?.call(())?;
+    Ok(Progress {
+        outcome,
+        next_timer: (next_timer >= 0.0).then(|| Duration::from_millis(next_timer as u64)),
+        requests: serde_json::from_str(&requests)?,
+    })
+}
+
+/// Creates a runtime and context and evaluates the script (or the
+/// passthrough script) in it.
+fn load_script(
+    script_path: Option<&Path>,
+    timeout: Duration,
+    deadline: &Rc<Cell<Option<Instant>>>,
+) -> Result<LoadedScript> {
+    let mut files = Vec::new();
+    let script = match script_path {
+        Some(path) => {
+            files.push((path.to_path_buf(), file_stamp(path)));
+            fs::read_to_string(path)
+                .with_context(|| format!("Failed to read script file: {}", path.display()))?
+        }
+        None => PASSTHROUGH_SCRIPT.to_string(),
+    };
+
+    let runtime = Runtime::new().with_context(|| "Failed to create JS runtime")?;
+    let imported = Rc::new(RefCell::new(Vec::new()));
+    runtime.set_loader(
+        RelativeResolver {
+            imported: imported.clone(),
+        },
+        ScriptLoader::default().with_extension("mjs"),
+    );
+    let interrupt = deadline.clone();
+    runtime.set_interrupt_handler(Some(Box::new(move || {
+        interrupt.get().is_some_and(|d| Instant::now() >= d)
+    })));
+    let ctx = Context::full(&runtime).with_context(|| "Failed to create JS context")?;
+
+    let module_path = match script_path {
+        Some(path)
+            if path.extension().is_some_and(|ext| ext == "mjs") || is_module_source(&script) =>
+        {
+            Some(
+                fs::canonicalize(path).with_context(|| {
+                    format!("Failed to resolve script path: {}", path.display())
+                })?,
+            )
+        }
+        _ => None,
+    };
+
+    deadline.set(Some(Instant::now() + timeout));
+    let has_reply = ctx.with(|ctx| {
+        inject_console(&ctx)?;
+        ctx.eval::<(), _>(STATE_PRELUDE)
+            .map_err(|e| anyhow::anyhow!("JS state setup error: {:?}", e))?;
+        ctx.eval::<(), _>(EVENT_LOOP_PRELUDE)
+            .map_err(|e| anyhow::anyhow!("JS event loop setup error: {:?}", e))?;
+        match &module_path {
+            Some(path) => load_module(&ctx, path, &script)?,
+            None => ctx
+                .eval::<(), _>(script.as_str())
+                .map_err(|e| anyhow::anyhow!("JS script error: {}", caught(&ctx, e)))?,
+        }
+        if ctx.globals().get::<_, Function>("transform").is_err() {
+            anyhow::bail!("the script does not define a transform(e) function");
+        }
+        Ok::<bool, anyhow::Error>(ctx.globals().get::<_, Function>("reply").is_ok())
+    });
+    deadline.set(None);
+    let has_reply = has_reply.with_context(|| "Failed to initialize JS context")?;
+
+    files.extend(imported.take());
+    Ok(LoadedScript {
+        ctx,
+        has_reply,
+        files,
+    })
+}
+
+fn file_stamp(path: &Path) -> FileStamp {
+    let contents = fs::read(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+fn dump_state(ctx: &Context) -> Result<String> {
+    ctx.with(|ctx| {
+        let dump: Function = ctx.eval("state.__dump")?;
+        Ok(dump.call(())?)
+    })
+}
+
+fn load_state(ctx: &Context, json: &str) -> Result<()> {
+    ctx.with(|ctx| {
+        let load: Function = ctx.eval("state.__load")?;
+        load.call::<_, ()>((json,))
+            .map_err(|e| anyhow::anyhow!(caught(&ctx, e)))
+    })
+}
+
+/// Builds the `e` argument: the parsed event plus `bytes` (`Uint8Array` or null).
+fn event_value<'js>(ctx: &Ctx<'js>, event_json: &str, raw: Option<&[u8]>) -> Result<Value<'js>> {
+    let value = ctx
+        .json_parse(event_json)
+        .map_err(|e| anyhow::anyhow!("JS JSON parse error: {:?}", e))?;
+    if let Some(obj) = value.as_object() {
+        match raw {
+            Some(raw) => obj.set("bytes", TypedArray::<u8>::new_copy(ctx.clone(), raw)?)?,
+            None => obj.set("bytes", Value::new_null(ctx.clone()))?,
+        }
+    }
+    Ok(value)
+}
+
+/// Whether a script uses ES module syntax at the top level.
+fn is_module_source(script: &str) -> bool {
+    script.lines().any(|line| {
+        let line = line.trim_start();
+        ["import ", "import{", "import\"", "import'", "export "]
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+    })
+}
+
+/// Evaluates the entry module and exposes its `transform`/`reply` exports as globals.
+fn load_module(ctx: &Ctx, path: &Path, source: &str) -> Result<()> {
+    let name = path.to_string_lossy().into_owned();
+    let (module, promise) = Module::declare(ctx.clone(), name, source)
+        .and_then(|module| module.eval())
+        .map_err(|e| anyhow::anyhow!("JS module error: {}", caught(ctx, e)))?;
+    promise
+        .finish::<()>()
+        .map_err(|e| anyhow::anyhow!("JS module error: {}", caught(ctx, e)))?;
+    let namespace: Object = module.namespace()?;
+    for export in ["transform", "reply"] {
+        if let Ok(func) = namespace.get::<_, Function>(export) {
+            ctx.globals().set(export, func)?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces a bare `Exception` error with the thrown value's message.
+fn caught(ctx: &Ctx, error: rquickjs::Error) -> String {
+    if error.is_exception() {
+        let exception = ctx.catch();
+        if let Some(ex) = exception.as_exception() {
+            return ex.to_string().trim_end().to_string();
+        }
+        return match exception.get::<rquickjs::Coerced<String>>() {
+            Ok(text) => text.0,
+            Err(_) => format!("{:?}", exception),
+        };
+    }
+    error.to_string()
+}
+
+/// Resolves `./` and `../` imports against the importing file's directory,
+/// and records the files for `--watch-script`.
+struct RelativeResolver {
+    imported: Rc<RefCell<Vec<(PathBuf, FileStamp)>>>,
+}
+
+impl Resolver for RelativeResolver {
+    fn resolve<'js>(
+        &mut self,
+        _ctx: &Ctx<'js>,
+        base: &str,
+        name: &str,
+    ) -> rquickjs::Result<String> {
+        let dir = Path::new(base).parent().unwrap_or(Path::new("."));
+        let mut path = dir.join(name);
+        if path.extension().is_none() {
+            path.set_extension("js");
+        }
+        let path =
+            fs::canonicalize(&path).map_err(|_| rquickjs::Error::new_resolving(base, name))?;
+        let stamp = file_stamp(&path);
+        self.imported.borrow_mut().push((path.clone(), stamp));
+        Ok(path.to_string_lossy().into_owned())
     }
 }
 
@@ -75,24 +571,33 @@ fn inject_console(ctx: &Ctx) -> Result<()> {
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_passthrough_text() {
-        let engine = JsEngine::new(None).unwrap();
+    /// The text of a transform result that went to the default output.
+    fn single(routed: Vec<Routed>) -> Option<String> {
+        match routed.as_slice() {
+            [] => None,
+            [Routed { target: None, data }] => data.clone(),
+            other => panic!("unexpected routes: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_text() {
+        let engine = JsEngine::new(None, ScriptConfig::default()).unwrap();
         let event = r#"{"text":"hello","binary":null,"timestamp":"2026-01-01T00:00:00Z","type":"text","source":"single"}"#;
-        let result = engine.transform(event).unwrap();
+        let result = single(engine.transform(event, None).await.unwrap());
         assert_eq!(result, Some("hello".to_string()));
     }
 
-    #[test]
-    fn test_passthrough_binary() {
-        let engine = JsEngine::new(None).unwrap();
+    #[tokio::test]
+    async fn test_passthrough_binary() {
+        let engine = JsEngine::new(None, ScriptConfig::default()).unwrap();
         let event = r#"{"text":null,"binary":"deadbeef","timestamp":"2026-01-01T00:00:00Z","type":"binary","source":"single"}"#;
-        let result = engine.transform(event).unwrap();
+        let result = single(engine.transform(event, None).await.unwrap());
         assert_eq!(result, Some("deadbeef".to_string()));
     }
 
-    #[test]
-    fn test_custom_transform() {
+    #[tokio::test]
+    async fn test_custom_transform() {
         let script = r#"
             function transform(e) {
                 var data = JSON.parse(e.text);
@@ -101,17 +606,17 @@ mod tests {
         "#;
         let dir = std::env::temp_dir().join("notir_test_transform.js");
         std::fs::write(&dir, script).unwrap();
-        let engine = JsEngine::new(Some(dir.to_str().unwrap())).unwrap();
+        let engine = JsEngine::new(Some(dir.to_str().unwrap()), ScriptConfig::default()).unwrap();
         let event = r#"{"text":"{\"msg\":\"hi\"}","binary":null,"timestamp":"2026-01-01T00:00:00Z","type":"text","source":"single"}"#;
-        let result = engine.transform(event).unwrap();
+        let result = single(engine.transform(event, None).await.unwrap());
         assert!(result.is_some());
         let parsed: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
         assert_eq!(parsed["receivedAt"], "2026-01-01T00:00:00Z");
         assert_eq!(parsed["payload"]["msg"], "hi");
     }
 
-    #[test]
-    fn test_filter_returns_null() {
+    #[tokio::test]
+    async fn test_filter_returns_null() {
         let script = r#"
             function transform(e) {
                 return null;
@@ -119,10 +624,296 @@ mod tests {
         "#;
         let dir = std::env::temp_dir().join("notir_test_filter.js");
         std::fs::write(&dir, script).unwrap();
-        let engine = JsEngine::new(Some(dir.to_str().unwrap())).unwrap();
+        let engine = JsEngine::new(Some(dir.to_str().unwrap()), ScriptConfig::default()).unwrap();
         let event =
             r#"{"text":"hello","binary":null,"timestamp":"","type":"text","source":"single"}"#;
-        let result = engine.transform(event).unwrap();
+        let result = single(engine.transform(event, None).await.unwrap());
         assert_eq!(result, None);
     }
+
+    #[tokio::test]
+    async fn test_reply_function() {
+        let script = r#"
+            function transform(e) { return e.text; }
+            function reply(e) {
+                return e.text === "ping" ? "pong" : null;
+            }
+        "#;
+        let dir = std::env::temp_dir().join("notir_test_reply.js");
+        std::fs::write(&dir, script).unwrap();
+        let engine = JsEngine::new(Some(dir.to_str().unwrap()), ScriptConfig::default()).unwrap();
+        assert!(engine.has_reply());
+        let ping =
+            r#"{"text":"ping","binary":null,"timestamp":"","type":"text","source":"single"}"#;
+        assert_eq!(
+            engine.reply(ping, None).await.unwrap(),
+            Some("pong".to_string())
+        );
+        let other = r#"{"text":"hi","binary":null,"timestamp":"","type":"text","source":"single"}"#;
+        assert_eq!(engine.reply(other, None).await.unwrap(), None);
+
+        let passthrough = JsEngine::new(None, ScriptConfig::default()).unwrap();
+        assert!(!passthrough.has_reply());
+        assert_eq!(passthrough.reply(ping, None).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_binary_bytes_exposed_as_uint8array() {
+        let script = r#"
+            function transform(e) {
+                if (e.bytes === null) return "none";
+                return (e.bytes instanceof Uint8Array) + ":" + e.bytes.length + ":" + e.bytes[1];
+            }
+        "#;
+        let dir = std::env::temp_dir().join("notir_test_bytes.js");
+        std::fs::write(&dir, script).unwrap();
+        let engine = JsEngine::new(Some(dir.to_str().unwrap()), ScriptConfig::default()).unwrap();
+        let event =
+            r#"{"text":null,"binary":"cafe","timestamp":"","type":"binary","source":"single"}"#;
+        let result = single(engine.transform(event, Some(&[0xca, 0xfe])).await.unwrap());
+        assert_eq!(result, Some("true:2:254".to_string()));
+        let text = r#"{"text":"hi","binary":null,"timestamp":"","type":"text","source":"single"}"#;
+        assert_eq!(
+            single(engine.transform(text, None).await.unwrap()),
+            Some("none".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_module_imports_and_state() {
+        let dir = std::env::temp_dir().join("notir_test_module");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(
+            dir.join("lib/util.js"),
+            "export function shout(s) { return s.toUpperCase(); }",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.js"),
+            r##"
+                import { shout } from "./lib/util.js";
+                export function transform(e) {
+                    if (state.has("seen:" + e.text)) return null;
+                    state.set("seen:" + e.text, true, 60);
+                    return shout(e.text) + "#" + state.incr("count");
+                }
+            "##,
+        )
+        .unwrap();
+        let state_file = dir.join("state.json");
+        let script = dir.join("main.js");
+
+        let mut engine =
+            JsEngine::new(Some(script.to_str().unwrap()), ScriptConfig::default()).unwrap();
+        engine.set_state_file(&state_file).unwrap();
+        let event = r#"{"text":"hi","binary":null,"timestamp":"","type":"text","source":"single"}"#;
+        assert_eq!(
+            single(engine.transform(event, None).await.unwrap()),
+            Some("HI#1".to_string())
+        );
+        assert_eq!(single(engine.transform(event, None).await.unwrap()), None);
+        engine.save_state().unwrap();
+
+        // A new engine picks up the dedup window and counter from disk.
+        let mut engine =
+            JsEngine::new(Some(script.to_str().unwrap()), ScriptConfig::default()).unwrap();
+        engine.set_state_file(&state_file).unwrap();
+        assert_eq!(single(engine.transform(event, None).await.unwrap()), None);
+        let other = r#"{"text":"yo","binary":null,"timestamp":"","type":"text","source":"single"}"#;
+        assert_eq!(
+            single(engine.transform(other, None).await.unwrap()),
+            Some("YO#2".to_string())
+        );
+    }
+
+    fn engine_with(name: &str, script: &str, config: ScriptConfig) -> JsEngine {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, script).unwrap();
+        JsEngine::new(Some(path.to_str().unwrap()), config).unwrap()
+    }
+
+    const HELLO: &str =
+        r#"{"text":"hello","binary":null,"timestamp":"","type":"text","source":"single"}"#;
+
+    #[tokio::test]
+    async fn test_async_transform_with_timers() {
+        let script = r#"
+            function sleep(ms) { return new Promise(function (r) { setTimeout(r, ms); }); }
+            async function transform(e) {
+                var order = [];
+                setTimeout(function (x) { order.push(x); }, 20, "late");
+                setTimeout(function (x) { order.push(x); }, 5, "early");
+                await sleep(30);
+                return e.text + ":" + order.join(",");
+            }
+        "#;
+        let engine = engine_with("notir_test_async.js", script, ScriptConfig::default());
+        assert_eq!(
+            single(engine.transform(HELLO, None).await.unwrap()),
+            Some("hello:early,late".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_runaway_scripts_are_stopped() {
+        let script = r#"
+            function transform(e) {
+                if (e.text === "spin") { while (true) {} }
+                if (e.text === "wait") return new Promise(function (r) { setTimeout(r, 60000); });
+                if (e.text === "stuck") return new Promise(function () {});
+                return e.text;
+            }
+        "#;
+        let config = ScriptConfig {
+            timeout: Duration::from_millis(200),
+            ..ScriptConfig::default()
+        };
+        let engine = engine_with("notir_test_runaway.js", script, config);
+        let event = |text: &str| HELLO.replace("hello", text);
+
+        let started = Instant::now();
+        assert!(engine.transform(&event("spin"), None).await.is_err());
+        assert!(engine.transform(&event("wait"), None).await.is_err());
+        assert!(engine.transform(&event("stuck"), None).await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+        // The engine keeps working afterwards.
+        assert_eq!(
+            single(engine.transform(HELLO, None).await.unwrap()),
+            Some("hello".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_respects_allowlist() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = socket.read(&mut buf).await;
+            let body = r#"{"city":"Berlin"}"#;
+            let resp = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(resp.as_bytes()).await.unwrap();
+        });
+
+        let script = format!(
+            r#"
+            async function transform(e) {{
+                try {{
+                    await fetch("http://blocked.example.com/");
+                    return "not blocked";
+                }} catch (err) {{}}
+                var resp = await fetch("http://127.0.0.1:{}/geo?ip=" + e.text);
+                var data = await resp.json();
+                return resp.status + " " + resp.headers.get("Content-Type") + " " + data.city;
+            }}
+        "#,
+            port
+        );
+        let config = ScriptConfig {
+            fetch_allow: vec!["127.0.0.1".to_string()],
+            ..ScriptConfig::default()
+        };
+        let engine = engine_with("notir_test_fetch.js", &script, config);
+        assert_eq!(
+            single(engine.transform(HELLO, None).await.unwrap()),
+            Some("200 application/json Berlin".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transform_routes() {
+        let script = r#"
+            function transform(e) {
+                return [
+                    e.text,
+                    null,
+                    { target: "file:alerts", data: { level: "high" } },
+                    { target: "stdout" },
+                    { target: "exec:notify", data: null },
+                ];
+            }
+        "#;
+        let engine = engine_with("notir_test_routes.js", script, ScriptConfig::default());
+        let route = |target: Option<&str>, data: Option<&str>| Routed {
+            target: target.map(str::to_string),
+            data: data.map(str::to_string),
+        };
+        assert_eq!(
+            engine.transform(HELLO, None).await.unwrap(),
+            vec![
+                route(None, Some("hello")),
+                route(Some("file:alerts"), Some(r#"{"level":"high"}"#)),
+                route(Some("stdout"), None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watched_script_reloads_only_when_valid() {
+        let dir = std::env::temp_dir().join("notir_test_reload");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("main.js");
+        let helper = dir.join("tag.js");
+        std::fs::write(&helper, r#"export const tag = "v1";"#).unwrap();
+        std::fs::write(
+            &main,
+            r#"import { tag } from "./tag.js";
+            export function transform(e) { return tag + ":" + state.incr("n"); }"#,
+        )
+        .unwrap();
+        let config = ScriptConfig {
+            watch: true,
+            ..ScriptConfig::default()
+        };
+        let engine = JsEngine::new(Some(main.to_str().unwrap()), config).unwrap();
+        assert_eq!(
+            single(engine.transform(HELLO, None).await.unwrap()),
+            Some("v1:1".into())
+        );
+
+        // A version without transform() is rejected; the old one keeps running.
+        std::fs::write(&main, "function nope() {}").unwrap();
+        engine.reload_if_changed();
+        assert_eq!(
+            single(engine.transform(HELLO, None).await.unwrap()),
+            Some("v1:2".into())
+        );
+
+        std::fs::write(
+            &main,
+            r#"import { tag } from "./tag.js";
+            export function transform(e) { return tag + "/" + state.incr("n"); }"#,
+        )
+        .unwrap();
+        engine.reload_if_changed();
+        assert_eq!(
+            single(engine.transform(HELLO, None).await.unwrap()),
+            Some("v1/3".into())
+        );
+
+        // Editing an imported module reloads too, even when neither its
+        // length nor its modification time changes.
+        let modified = std::fs::metadata(&helper).unwrap().modified().unwrap();
+        std::fs::write(&helper, r#"export const tag = "v2";"#).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&helper)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        engine.reload_if_changed();
+        assert_eq!(
+            single(engine.transform(HELLO, None).await.unwrap()),
+            Some("v2/4".into())
+        );
+    }
 }
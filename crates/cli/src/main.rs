@@ -1,35 +1,230 @@
 use anyhow::Result;
 use clap::Parser;
+use std::process::ExitCode;
+use std::time::Duration;
 use tracing_subscriber::EnvFilter;
 
 mod args;
+mod backoff;
+mod client;
+mod config;
+mod exec;
+mod fetch;
+mod js_fetch;
 mod js_runtime;
 mod output;
+mod proxy;
+mod publish;
+mod replay;
+mod rotation;
+mod routes;
 mod script_api;
+mod share;
+mod test_script;
+mod util;
+mod webhook;
 mod ws_client;
 
-use args::Cli;
+use args::{Cli, Command, ReplayArgs, SubArgs};
+use client::ServerClient;
+use config::Subscription;
+use tracing::Instrument;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
 
-    // Initialize logging
+    // Initialize logging (stderr, so stdout carries only messages)
     let default_filter = if cli.verbose { "debug" } else { "info" };
     tracing_subscriber::fmt()
         .with_env_filter(
             EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter)),
         )
+        .with_writer(std::io::stderr)
         .init();
 
-    let js_engine = js_runtime::JsEngine::new(cli.script.as_deref())?;
-    let mut writer =
-        output::OutputWriter::new(cli.output, cli.file_mode, &cli.output_dir, &cli.id)?;
+    let server = client::ServerClient::new(&cli)?;
+    match &cli.command {
+        Command::Sub(args) => subscribe_all(&server, args).await?,
+        Command::Pub(args) => return publish::run(&server, args).await,
+        Command::Share(args) => share::run(&server, args).await?,
+        Command::Fetch(args) => fetch::run(&server, args).await?,
+        Command::TestScript(args) => return test_script::run(&server, args).await,
+        Command::Replay(args) if args.publish => return replay::publish(&server, args).await,
+        Command::Replay(args) => {
+            let sub = config::single(&args.sub)?;
+            subscribe(&server, &sub, Some(args)).await?
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Follows every channel from `--id`, `--sub` and `--config` concurrently.
+/// Each subscription has its own script, outputs and reconnect loop; one that
+/// stops does not stop the others.
+async fn subscribe_all(server: &ServerClient, cli: &SubArgs) -> Result<()> {
+    let subs = config::subscriptions(cli)?;
+    if let [sub] = subs.as_slice() {
+        return subscribe(server, sub, None).await;
+    }
+    let runs = subs.iter().map(|sub| {
+        let span = tracing::info_span!("sub", channel = %format!("{}:{}", sub.mode, sub.id));
+        subscribe(server, sub, None).instrument(span)
+    });
+    let results = futures_util::future::join_all(runs).await;
+    let mut failed = 0;
+    for (sub, result) in subs.iter().zip(results) {
+        if let Err(e) = result {
+            tracing::error!("Subscription {}:{} failed: {:#}", sub.mode, sub.id, e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} subscriptions failed", failed, subs.len());
+    }
+    Ok(())
+}
+
+/// Runs the subscriber pipeline on messages from the server, or from a
+/// recording when `replay` is given.
+async fn subscribe(
+    server: &ServerClient,
+    sub: &Subscription,
+    replay: Option<&ReplayArgs>,
+) -> Result<()> {
+    let cli = &sub.args;
+    let id = sub.id.as_str();
+    if replay.is_some() && cli.record.is_some() {
+        anyhow::bail!("--record is not supported by replay");
+    }
+    let script_config = js_runtime::ScriptConfig {
+        timeout: Duration::from_millis(cli.script_timeout_ms),
+        fetch_allow: cli.fetch_allow.clone(),
+        watch: cli.watch_script,
+        proxy: server.http_proxy(),
+    };
+    let mut js_engine = js_runtime::JsEngine::new(cli.script.as_deref(), script_config)?;
+    if let Some(path) = &cli.state_file {
+        js_engine.set_state_file(path)?;
+    }
+    let webhook = cli.webhook_url.as_ref().map(|url| webhook::WebhookConfig {
+        url: url.clone(),
+        headers: cli.webhook_headers.clone(),
+        retries: cli.webhook_retries,
+        backoff: Duration::from_millis(cli.webhook_backoff_ms),
+        spool: sub.webhook_spool(),
+        proxy: server.http_proxy(),
+    });
+    let rotation = rotation::RotationConfig {
+        template: sub.file_template(),
+        max_size: cli.rotate_size,
+        every: cli.rotate_every,
+        max_files: cli.max_files,
+        compress: cli.compress,
+    };
+    rotation.validate()?;
+    let output = output::OutputConfig {
+        format: cli.format,
+        mode: cli.output,
+        file_mode: cli.file_mode,
+        output_dir: cli.output_dir.clone(),
+        raw_binary: cli.raw_binary,
+    };
+    let mut routes = routes::Routes::default();
+    for (name, command) in &cli.route_exec {
+        routes.execs.insert(name.clone(), exec_hook(cli, command));
+    }
+    for (name, url) in &cli.route_webhook {
+        let config = webhook::WebhookConfig {
+            url: url.clone(),
+            headers: cli.webhook_headers.clone(),
+            retries: cli.webhook_retries,
+            backoff: Duration::from_millis(cli.webhook_backoff_ms),
+            spool: sub.route_spool(name),
+            proxy: server.http_proxy(),
+        };
+        routes
+            .webhooks
+            .insert(name.clone(), webhook::Webhook::new(config)?);
+    }
+    let mut writer = output::OutputWriter::new(output, id, rotation, webhook, routes)?;
+    if cli.exec_reply && sub.mode != args::SubscriptionMode::Single {
+        anyhow::bail!("--exec-reply is only supported in single mode");
+    }
+    let exec = cli.exec.as_deref().map(|command| exec_hook(cli, command));
+    let mut recorder = cli
+        .record
+        .as_deref()
+        .map(|path| replay::Recorder::open(path, cli.binary_encoding))
+        .transpose()?;
+
+    let handler = ws_client::EventHandler::new(
+        id,
+        sub.mode,
+        cli.binary_encoding,
+        &js_engine,
+        exec.as_ref(),
+        cli.exec_reply,
+    );
+    let run = async {
+        match replay {
+            Some(replay) => replay::feed(replay, &handler, &mut writer).await,
+            None => reconnect_loop(server, cli, &handler, &mut writer, recorder.as_mut()).await,
+        }
+    };
+    // On a signal the run is dropped mid-message, then outputs are flushed
+    // below; webhook messages still queued go to the spool.
+    let (result, interrupted) = tokio::select! {
+        result = run => (result, false),
+        signal = shutdown_signal() => {
+            tracing::info!("Received {}, flushing outputs", signal);
+            (Ok(()), true)
+        }
+    };
+    if let Some(exec) = exec {
+        exec.finish().await;
+    }
+    writer.finish(interrupted).await;
+    if let Err(e) = js_engine.save_state() {
+        tracing::error!("Failed to save JS state: {:#}", e);
+    }
+    result
+}
 
-    let mut attempts = 0u32;
+fn exec_hook(cli: &SubArgs, command: &str) -> exec::ExecHook {
+    exec::ExecHook::new(
+        command,
+        cli.exec_concurrency,
+        Duration::from_secs(cli.exec_timeout),
+        cli.exec_max_queue,
+    )
+}
+
+async fn reconnect_loop(
+    server: &ServerClient,
+    cli: &SubArgs,
+    handler: &ws_client::EventHandler<'_>,
+    writer: &mut output::OutputWriter,
+    mut recorder: Option<&mut replay::Recorder>,
+) -> Result<()> {
+    let mut backoff = backoff::Backoff::new(
+        Duration::from_secs(cli.reconnect_interval),
+        Duration::from_secs(cli.reconnect_max_interval),
+        Duration::from_secs(cli.stable_after),
+    );
+    let ping_timeout = (cli.ping_timeout > 0).then(|| Duration::from_secs(cli.ping_timeout));
 
     loop {
-        match ws_client::run_client(&cli.server, &cli.id, cli.mode, &js_engine, &mut writer).await {
+        let result = ws_client::run_client(
+            server,
+            handler,
+            writer,
+            recorder.as_deref_mut(),
+            &mut backoff,
+            ping_timeout,
+        )
+        .await;
+        match result {
             Ok(()) => {
                 tracing::info!("Disconnected");
                 if !cli.reconnect {
@@ -37,35 +232,61 @@ async fn main() -> Result<()> {
                 }
             }
             Err(e) => {
-                tracing::error!("Connection error: {}", e);
+                tracing::error!("Connection error: {:#}", e);
                 if !cli.reconnect {
                     return Err(e);
                 }
             }
         }
 
-        // Reconnect logic
-        if cli.reconnect {
-            attempts += 1;
-            if cli.max_reconnect > 0 && attempts > cli.max_reconnect {
-                tracing::error!("Max reconnect attempts ({}) reached", cli.max_reconnect);
-                break;
-            }
-            tracing::info!(
-                "Reconnecting in {} seconds (attempt {}/{})",
-                cli.reconnect_interval,
-                attempts,
-                if cli.max_reconnect > 0 {
-                    cli.max_reconnect.to_string()
-                } else {
-                    "unlimited".to_string()
-                }
-            );
-            tokio::time::sleep(tokio::time::Duration::from_secs(cli.reconnect_interval)).await;
-        } else {
+        let attempt = backoff.disconnected();
+        if cli.max_reconnect > 0 && attempt > cli.max_reconnect {
+            tracing::error!("Max reconnect attempts ({}) reached", cli.max_reconnect);
             break;
         }
+        let delay = backoff.delay();
+        tracing::info!(
+            "Reconnecting in {:.1} seconds (attempt {}/{})",
+            delay.as_secs_f64(),
+            attempt,
+            if cli.max_reconnect > 0 {
+                cli.max_reconnect.to_string()
+            } else {
+                "unlimited".to_string()
+            }
+        );
+        tokio::time::sleep(delay).await;
     }
 
     Ok(())
 }
+
+/// Resolves with the signal's name on Ctrl-C / SIGINT, or SIGTERM on Unix.
+async fn shutdown_signal() -> &'static str {
+    let interrupt = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let terminate = async {
+            match signal(SignalKind::terminate()) {
+                Ok(mut term) => {
+                    term.recv().await;
+                }
+                Err(_) => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = interrupt => "SIGINT",
+            _ = terminate => "SIGTERM",
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        interrupt.await;
+        "Ctrl-C"
+    }
+}
@@ -1,98 +1,663 @@
 use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
 
-use crate::args::{FileMode, OutputMode};
+use crate::args::{FileMode, OutputFormat, OutputMode, RotateEvery};
+use crate::exec::ExecEnv;
+use crate::rotation::{RotatingFile, RotationConfig, period_key, prune_period_dirs};
+use crate::routes::{Routes, Target};
+use crate::script_api::WsEvent;
+use crate::webhook::{Webhook, WebhookConfig};
+
+/// Where and how transformed messages are written.
+pub struct OutputConfig {
+    pub format: OutputFormat,
+    pub mode: OutputMode,
+    pub file_mode: FileMode,
+    pub output_dir: String,
+    /// Write untransformed binary messages as raw bytes to stdout and append files
+    pub raw_binary: bool,
+}
 
 pub struct OutputWriter {
+    id: String,
+    format: OutputFormat,
+    raw_binary: bool,
     print_stdout: bool,
     file_writer: Option<FileWriter>,
+    webhook: Option<Webhook>,
+    output_dir: PathBuf,
+    rotation: RotationConfig,
+    /// `file:NAME` targets, opened on first use
+    named_files: HashMap<String, RotatingFile>,
+    routes: Routes,
 }
 
 enum FileWriter {
-    Append { file: std::fs::File },
-    Individual { dir: PathBuf, seq: u64 },
+    Append(RotatingFile),
+    Individual(IndividualFiles),
+}
+
+/// One file per message, named from a template and numbered by `seq`.
+struct IndividualFiles {
+    id: String,
+    template: String,
+    root: PathBuf,
+    dir: PathBuf,
+    seq: u64,
+    every: Option<RotateEvery>,
+    period: String,
+    max_dirs: usize,
+}
+
+/// A message as handed to file output.
+struct Payload<'a> {
+    text: &'a str,
+    /// Raw bytes of an untransformed binary message, written instead of `text`
+    raw: Option<&'a [u8]>,
+    msg_type: &'a str,
 }
 
 impl OutputWriter {
     pub fn new(
-        output_mode: OutputMode,
-        file_mode: FileMode,
-        output_dir: &str,
+        config: OutputConfig,
         id: &str,
+        rotation: RotationConfig,
+        webhook: Option<WebhookConfig>,
+        routes: Routes,
     ) -> Result<Self> {
+        let output_mode = config.mode;
         let print_stdout = matches!(output_mode, OutputMode::Stdout | OutputMode::Both);
 
         let file_writer = match output_mode {
-            OutputMode::Stdout => None,
-            OutputMode::File | OutputMode::Both => {
-                Some(FileWriter::new(file_mode, output_dir, id)?)
-            }
+            OutputMode::Stdout | OutputMode::Webhook => None,
+            OutputMode::File | OutputMode::Both => Some(FileWriter::new(
+                config.file_mode,
+                &config.output_dir,
+                id,
+                rotation.clone(),
+            )?),
+        };
+
+        let webhook = match (output_mode, webhook) {
+            (OutputMode::Webhook, Some(config)) => Some(Webhook::new(config)?),
+            (OutputMode::Webhook, None) => anyhow::bail!("Webhook output requires a URL"),
+            _ => None,
         };
 
         Ok(Self {
+            id: id.to_string(),
+            format: config.format,
+            raw_binary: config.raw_binary,
             print_stdout,
             file_writer,
+            webhook,
+            output_dir: PathBuf::from(config.output_dir),
+            rotation,
+            named_files: HashMap::new(),
+            routes,
         })
     }
 
-    pub fn write_message(&mut self, msg: &str) -> Result<()> {
+    /// Writes the transform result for `event`, or the transform error that
+    /// replaced it, in the configured format.
+    pub fn write_event(
+        &mut self,
+        event: &WsEvent,
+        output: &str,
+        error: Option<&str>,
+    ) -> Result<()> {
+        match self.format {
+            OutputFormat::Text => {
+                // Binary passthrough: the transform returned the encoded payload unchanged.
+                let raw = match (&event.raw, &event.binary) {
+                    (Some(raw), Some(hex)) if error.is_none() && hex == output => {
+                        Some(raw.as_slice())
+                    }
+                    _ => None,
+                };
+                self.write_payload(&Payload {
+                    text: output,
+                    raw,
+                    msg_type: event.msg_type,
+                })
+            }
+            OutputFormat::Jsonl => {
+                let line = jsonl_line(event, output, error)?;
+                self.write_payload(&Payload {
+                    text: &line,
+                    raw: None,
+                    msg_type: "text",
+                })
+            }
+        }
+    }
+
+    fn write_payload(&mut self, payload: &Payload) -> Result<()> {
+        let msg = payload.text;
         if self.print_stdout {
-            println!("{}", msg);
+            match payload.raw {
+                Some(raw) if self.raw_binary => {
+                    let mut stdout = std::io::stdout().lock();
+                    stdout.write_all(raw)?;
+                    stdout.flush()?;
+                }
+                _ => println!("{}", msg),
+            }
         }
         if let Some(ref mut fw) = self.file_writer {
-            fw.write_message(msg)?;
+            fw.write_payload(payload, self.raw_binary)?;
+        }
+        if let Some(ref webhook) = self.webhook {
+            webhook.send(msg);
         }
         Ok(())
     }
+
+    /// Writes `data` to a target returned by the transform. Stdout follows
+    /// `--format`; the other targets get `data` as-is. Returns false for an
+    /// `exec:`/`webhook:` name that was not declared.
+    pub fn write_routed(&mut self, target: Target, event: &WsEvent, data: &str) -> Result<bool> {
+        match target {
+            Target::Default => self.write_event(event, data, None)?,
+            Target::Stdout => match self.format {
+                OutputFormat::Text => println!("{}", data),
+                OutputFormat::Jsonl => println!("{}", jsonl_line(event, data, None)?),
+            },
+            Target::File(name) => {
+                let file = match self.named_files.entry(name.to_string()) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        std::fs::create_dir_all(&self.output_dir)?;
+                        let rotation = RotationConfig {
+                            template: format!("{{id}}.{}.txt", name),
+                            ..self.rotation.clone()
+                        };
+                        entry.insert(RotatingFile::open(&self.output_dir, &self.id, rotation)?)
+                    }
+                };
+                file.write_line(data)?;
+            }
+            Target::Exec(name) => match self.routes.execs.get(name) {
+                Some(exec) => exec.submit(data, ExecEnv::new(&self.id, event)),
+                None => return Ok(false),
+            },
+            Target::Webhook(name) => match self.routes.webhooks.get(name) {
+                Some(webhook) => webhook.send(data),
+                None => return Ok(false),
+            },
+        }
+        Ok(true)
+    }
+
+    /// Waits for pending webhook deliveries and routed commands. When
+    /// `interrupted`, undelivered webhook messages are spooled instead.
+    pub async fn finish(self, interrupted: bool) {
+        if let Some(webhook) = self.webhook {
+            webhook.finish(interrupted).await;
+        }
+        self.routes.finish(interrupted).await;
+    }
+}
+
+/// One line of `--format jsonl` output.
+#[derive(Serialize)]
+struct JsonlRecord<'a> {
+    #[serde(flatten)]
+    event: &'a WsEvent,
+    /// Transform result; null when the transform failed
+    result: Option<&'a str>,
+    /// Transform error message; null on success
+    error: Option<&'a str>,
+}
+
+fn jsonl_line(event: &WsEvent, output: &str, error: Option<&str>) -> Result<String> {
+    let record = JsonlRecord {
+        event,
+        result: if error.is_none() { Some(output) } else { None },
+        error,
+    };
+    Ok(serde_json::to_string(&record)?)
 }
 
 impl FileWriter {
-    fn new(file_mode: FileMode, output_dir: &str, id: &str) -> Result<Self> {
+    fn new(
+        file_mode: FileMode,
+        output_dir: &str,
+        id: &str,
+        rotation: RotationConfig,
+    ) -> Result<Self> {
         let dir = PathBuf::from(output_dir);
         std::fs::create_dir_all(&dir)?;
 
         match file_mode {
-            FileMode::Append => {
-                let path = dir.join(format!("{}.txt", id));
-                let file = std::fs::OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(&path)?;
-                Ok(FileWriter::Append { file })
-            }
+            FileMode::Append => Ok(FileWriter::Append(RotatingFile::open(&dir, id, rotation)?)),
             FileMode::Individual => {
-                let sub_dir = dir.join(id);
+                let root = dir.join(id);
+                let period = period_key(rotation.every, OffsetDateTime::now_utc());
+                let sub_dir = root.join(&period);
                 std::fs::create_dir_all(&sub_dir)?;
-                Ok(FileWriter::Individual {
+                let seq = next_seq(&sub_dir, &rotation.template, id);
+                if seq > 0 {
+                    tracing::info!("Resuming individual files at seq {}", seq);
+                }
+                Ok(FileWriter::Individual(IndividualFiles {
+                    id: id.to_string(),
+                    template: rotation.template,
+                    root,
                     dir: sub_dir,
-                    seq: 0,
-                })
+                    seq,
+                    every: rotation.every,
+                    period,
+                    max_dirs: rotation.max_files,
+                }))
             }
         }
     }
 
-    fn write_message(&mut self, msg: &str) -> Result<()> {
+    fn write_payload(&mut self, payload: &Payload, raw_binary: bool) -> Result<()> {
         match self {
-            FileWriter::Append { file } => {
-                writeln!(file, "{}", msg)?;
-            }
-            FileWriter::Individual { dir, seq } => {
-                let n = *seq;
-                *seq += 1;
-                let filename = format!("{:0>6}.txt", n);
-                let path = dir.join(&filename);
-                std::fs::write(&path, msg)?;
+            FileWriter::Append(file) => match payload.raw {
+                Some(raw) if raw_binary => file.write_raw(raw),
+                _ => file.write_line(payload.text),
+            },
+            FileWriter::Individual(files) => files.write(payload, OffsetDateTime::now_utc()),
+        }
+    }
+}
+
+impl IndividualFiles {
+    fn write(&mut self, payload: &Payload, now: OffsetDateTime) -> Result<()> {
+        if self.every.is_some() {
+            let current = period_key(self.every, now);
+            if current != self.period {
+                self.dir = self.root.join(&current);
+                std::fs::create_dir_all(&self.dir)?;
+                self.period = current;
+                self.seq = next_seq(&self.dir, &self.template, &self.id);
+                prune_period_dirs(&self.root, &self.dir, self.max_dirs);
             }
         }
-        Ok(())
+
+        let (bytes, ext) = match payload.raw {
+            Some(raw) => (raw, "bin"),
+            None => (payload.text.as_bytes(), text_extension(payload.text)),
+        };
+        let name = render_individual_name(
+            &self.template,
+            &self.id,
+            self.seq,
+            now,
+            payload.msg_type,
+            ext,
+        );
+        self.seq += 1;
+        let path = unique_path(self.dir.join(name));
+        write_atomic(&path, bytes)
     }
 }
 
-impl Drop for FileWriter {
-    fn drop(&mut self) {
-        if let FileWriter::Append { file } = self {
-            let _ = file.flush();
+fn text_extension(text: &str) -> &'static str {
+    let trimmed = text.trim_start();
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde::de::IgnoredAny>(text).is_ok()
+    {
+        "json"
+    } else {
+        "txt"
+    }
+}
+
+/// Renders an individual-mode file name. `{seq}` is zero-padded to six
+/// digits and `{timestamp}` is UTC with milliseconds (`20261018T130000123`).
+fn render_individual_name(
+    template: &str,
+    id: &str,
+    seq: u64,
+    now: OffsetDateTime,
+    msg_type: &str,
+    ext: &str,
+) -> String {
+    let timestamp = format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}{:03}",
+        now.year(),
+        now.month() as u8,
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second(),
+        now.millisecond()
+    );
+    crate::rotation::render_template(template, id, now)
+        .replace("{seq}", &format!("{:06}", seq))
+        .replace("{timestamp}", &timestamp)
+        .replace("{type}", msg_type)
+        .replace("{ext}", ext)
+}
+
+/// Next free `seq` in `dir`: one past the highest seq found in file names
+/// that match `template`, or 0.
+fn next_seq(dir: &Path, template: &str, id: &str) -> u64 {
+    let pattern = NamePattern::parse(template, id);
+    if !pattern.has_seq() {
+        return 0;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| pattern.seq_of(&entry.file_name().to_string_lossy()))
+        .max()
+        .map_or(0, |seq| seq + 1)
+}
+
+/// A file name template compiled for matching existing names.
+struct NamePattern(Vec<NamePart>);
+
+#[derive(Debug, PartialEq)]
+enum NamePart {
+    Literal(String),
+    Seq,
+    Any,
+}
+
+impl NamePattern {
+    fn parse(template: &str, id: &str) -> Self {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            literal.push_str(&rest[..start]);
+            let after = &rest[start..];
+            let Some(end) = after.find('}') else {
+                break;
+            };
+            let part = match &after[1..end] {
+                "id" => {
+                    literal.push_str(id);
+                    None
+                }
+                "seq" => Some(NamePart::Seq),
+                "date" | "hour" | "timestamp" | "type" | "ext" => Some(NamePart::Any),
+                _ => {
+                    literal.push_str(&after[..=end]);
+                    None
+                }
+            };
+            if let Some(part) = part {
+                if !literal.is_empty() {
+                    parts.push(NamePart::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(part);
+            }
+            rest = &after[end + 1..];
         }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(NamePart::Literal(literal));
+        }
+        Self(parts)
+    }
+
+    fn has_seq(&self) -> bool {
+        self.0.contains(&NamePart::Seq)
+    }
+
+    fn seq_of(&self, name: &str) -> Option<u64> {
+        fn matches(parts: &[NamePart], name: &str, seq: &mut Option<u64>) -> bool {
+            let Some((first, rest)) = parts.split_first() else {
+                return name.is_empty();
+            };
+            match first {
+                NamePart::Literal(lit) => name
+                    .strip_prefix(lit.as_str())
+                    .is_some_and(|tail| matches(rest, tail, seq)),
+                NamePart::Seq => {
+                    let digits =
+                        name.len() - name.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                    (1..=digits).rev().any(|n| {
+                        let ok = matches(rest, &name[n..], seq);
+                        if ok && seq.is_none() {
+                            *seq = name[..n].parse().ok();
+                        }
+                        ok
+                    })
+                }
+                NamePart::Any => (0..=name.len())
+                    .filter(|&n| name.is_char_boundary(n))
+                    .any(|n| matches(rest, &name[n..], seq)),
+            }
+        }
+        let mut seq = None;
+        matches(&self.0, name, &mut seq).then_some(seq).flatten()
+    }
+}
+
+/// Appends `-N` before the extension if `path` is already taken.
+fn unique_path(path: PathBuf) -> PathBuf {
+    if !path.exists() {
+        return path;
+    }
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), format!(".{}", ext)),
+        _ => (name.clone(), String::new()),
+    };
+    (1..)
+        .map(|n| path.with_file_name(format!("{}-{}{}", stem, n, ext)))
+        .find(|candidate| !candidate.exists())
+        .expect("unbounded range")
+}
+
+/// Writes to a hidden temp file next to `path` and renames it into place,
+/// so directory watchers only ever see complete files.
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp = path.with_file_name(format!(".{}.tmp", name));
+    {
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(bytes)?;
+        file.sync_data()?;
+    }
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::BinaryEncoding;
+
+    #[test]
+    fn jsonl_records_carry_event_and_result() {
+        let dir = std::env::temp_dir().join("notir_test_jsonl");
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = OutputConfig {
+            format: OutputFormat::Jsonl,
+            mode: OutputMode::File,
+            file_mode: FileMode::Append,
+            output_dir: dir.to_str().unwrap().to_string(),
+            raw_binary: false,
+        };
+        let mut writer = OutputWriter::new(
+            config,
+            "u1",
+            RotationConfig::default(),
+            None,
+            Routes::default(),
+        )
+        .unwrap();
+        let event = WsEvent::from_text("hi".to_string(), "single", "u1");
+        writer.write_event(&event, "HI", None).unwrap();
+        writer
+            .write_event(&event, "[TRANSFORM_ERROR] hi", Some("boom"))
+            .unwrap();
+        drop(writer);
+
+        let content = std::fs::read_to_string(dir.join("u1.txt")).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["text"], "hi");
+        assert_eq!(lines[0]["type"], "text");
+        assert_eq!(lines[0]["source"], "single");
+        assert_eq!(lines[0]["result"], "HI");
+        assert!(lines[0]["error"].is_null());
+        assert!(lines[1]["result"].is_null());
+        assert_eq!(lines[1]["error"], "boom");
+    }
+
+    #[test]
+    fn individual_files_resume_seq_and_use_extensions() {
+        let dir = std::env::temp_dir().join("notir_test_individual");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("u1")).unwrap();
+        std::fs::write(dir.join("u1/000041.txt"), "old").unwrap();
+        std::fs::write(dir.join("u1/notes.md"), "ignored").unwrap();
+
+        let config = RotationConfig {
+            template: "{seq}.{ext}".to_string(),
+            ..Default::default()
+        };
+        let output = OutputConfig {
+            format: OutputFormat::Text,
+            mode: OutputMode::File,
+            file_mode: FileMode::Individual,
+            output_dir: dir.to_str().unwrap().to_string(),
+            raw_binary: false,
+        };
+        let mut writer = OutputWriter::new(output, "u1", config, None, Routes::default()).unwrap();
+        let json = WsEvent::from_text("{\"a\":1}".to_string(), "single", "u1");
+        writer.write_event(&json, "{\"a\":1}", None).unwrap();
+        let binary = WsEvent::from_binary(&[0xca, 0xfe], "single", "u1", BinaryEncoding::Hex);
+        writer.write_event(&binary, "cafe", None).unwrap();
+        writer.write_event(&binary, "transformed", None).unwrap();
+
+        assert_eq!(
+            std::fs::read(dir.join("u1/000042.json")).unwrap(),
+            b"{\"a\":1}"
+        );
+        assert_eq!(
+            std::fs::read(dir.join("u1/000043.bin")).unwrap(),
+            [0xca, 0xfe]
+        );
+        assert_eq!(
+            std::fs::read(dir.join("u1/000044.txt")).unwrap(),
+            b"transformed"
+        );
+        assert!(!dir.join("u1/.000044.txt.tmp").exists());
+    }
+
+    #[test]
+    fn raw_binary_appends_bytes_without_newline() {
+        let dir = std::env::temp_dir().join("notir_test_raw_binary");
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = OutputConfig {
+            format: OutputFormat::Text,
+            mode: OutputMode::File,
+            file_mode: FileMode::Append,
+            output_dir: dir.to_str().unwrap().to_string(),
+            raw_binary: true,
+        };
+        let mut writer = OutputWriter::new(
+            config,
+            "u1",
+            RotationConfig::default(),
+            None,
+            Routes::default(),
+        )
+        .unwrap();
+        let binary =
+            WsEvent::from_binary(&[0x00, 0x0a, 0xff], "single", "u1", BinaryEncoding::Base64);
+        writer.write_event(&binary, "AAr/", None).unwrap();
+        writer.write_event(&binary, "decoded", None).unwrap();
+        drop(writer);
+        assert_eq!(
+            std::fs::read(dir.join("u1.txt")).unwrap(),
+            b"\x00\x0a\xffdecoded\n"
+        );
+    }
+
+    #[test]
+    fn routed_files_are_opened_per_name() {
+        let dir = std::env::temp_dir().join("notir_test_routed_files");
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = OutputConfig {
+            format: OutputFormat::Text,
+            mode: OutputMode::File,
+            file_mode: FileMode::Append,
+            output_dir: dir.to_str().unwrap().to_string(),
+            raw_binary: false,
+        };
+        let mut writer = OutputWriter::new(
+            config,
+            "u1",
+            RotationConfig::default(),
+            None,
+            Routes::default(),
+        )
+        .unwrap();
+        let event = WsEvent::from_text("hi".to_string(), "single", "u1");
+        assert!(
+            writer
+                .write_routed(Target::File("alerts"), &event, "a1")
+                .unwrap()
+        );
+        assert!(
+            writer
+                .write_routed(Target::Default, &event, "main")
+                .unwrap()
+        );
+        assert!(
+            writer
+                .write_routed(Target::File("alerts"), &event, "a2")
+                .unwrap()
+        );
+        assert!(
+            !writer
+                .write_routed(Target::Exec("missing"), &event, "x")
+                .unwrap()
+        );
+        drop(writer);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("u1.alerts.txt")).unwrap(),
+            "a1\na2\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("u1.txt")).unwrap(),
+            "main\n"
+        );
+    }
+
+    #[test]
+    fn name_pattern_extracts_seq() {
+        let pattern = NamePattern::parse("{id}-{timestamp}-{seq}.{ext}", "u1");
+        assert_eq!(pattern.seq_of("u1-20261018T130000123-000007.json"), Some(7));
+        assert_eq!(pattern.seq_of("u2-20261018T130000123-000007.json"), None);
+        assert!(!NamePattern::parse("{timestamp}.{ext}", "u1").has_seq());
+        assert_eq!(
+            render_individual_name(
+                "{id}_{seq}_{type}.{ext}",
+                "u1",
+                3,
+                OffsetDateTime::UNIX_EPOCH,
+                "binary",
+                "bin"
+            ),
+            "u1_000003_binary.bin"
+        );
     }
 }
@@ -0,0 +1,123 @@
+use anyhow::{Context, Result, bail};
+use std::process::ExitCode;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+use crate::args::{PubArgs, SubscriptionMode};
+use crate::client::ServerClient;
+
+/// Exit code when no subscriber is online for the id (HTTP 404).
+const EXIT_NOT_FOUND: u8 = 3;
+/// Exit code when a ping_pong subscriber did not reply in time (HTTP 408).
+const EXIT_TIMEOUT: u8 = 4;
+
+pub async fn run(server: &ServerClient, args: &PubArgs) -> Result<ExitCode> {
+    if args.ping_pong && args.mode != SubscriptionMode::Single {
+        bail!("--ping-pong is only supported in single mode");
+    }
+
+    let mut query = vec![("id", args.id.as_str())];
+    if args.ping_pong {
+        query.push(("mode", "ping_pong"));
+    }
+    let url = server.http_url(&[&args.mode.to_string(), "pub"], &query);
+    let request = server
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, &args.content_type);
+
+    if !args.lines {
+        let body = read_body(args).await?;
+        let code = publish(&request, body, args.ping_pong, false).await?;
+        return Ok(ExitCode::from(code));
+    }
+
+    // Keep going after a failed line; the exit code reports the first failure.
+    let mut exit = 0;
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await.context("Failed to read stdin")? {
+        if line.is_empty() {
+            continue;
+        }
+        let code = publish(&request, line.into_bytes(), args.ping_pong, true).await?;
+        if exit == 0 {
+            exit = code;
+        }
+    }
+    Ok(ExitCode::from(exit))
+}
+
+async fn read_body(args: &PubArgs) -> Result<Vec<u8>> {
+    if let Some(body) = &args.body {
+        return Ok(body.clone().into_bytes());
+    }
+    if let Some(path) = &args.file {
+        return tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()));
+    }
+    let mut body = Vec::new();
+    tokio::io::stdin()
+        .read_to_end(&mut body)
+        .await
+        .context("Failed to read stdin")?;
+    Ok(body)
+}
+
+/// Sends one message and returns the process exit code it maps to.
+/// ping_pong replies are written to stdout as-is; `line_mode` adds a newline.
+async fn publish(
+    request: &reqwest::RequestBuilder,
+    body: Vec<u8>,
+    ping_pong: bool,
+    line_mode: bool,
+) -> Result<u8> {
+    let resp = request
+        .try_clone()
+        .expect("request body is set per message")
+        .body(body)
+        .send()
+        .await
+        .context("Failed to publish")?;
+    let status = resp.status();
+    let bytes = resp.bytes().await.context("Failed to read response")?;
+
+    if status.is_success() {
+        if ping_pong && !bytes.is_empty() {
+            let mut stdout = tokio::io::stdout();
+            stdout.write_all(&bytes).await?;
+            if line_mode {
+                stdout.write_all(b"\n").await?;
+            }
+            stdout.flush().await?;
+        }
+        return Ok(0);
+    }
+
+    let message = String::from_utf8_lossy(&bytes);
+    match exit_code_for(status.as_u16()) {
+        Some(code) => {
+            tracing::error!("Publish failed ({}): {}", status, message);
+            Ok(code)
+        }
+        None => bail!("Publish failed ({}): {}", status, message),
+    }
+}
+
+fn exit_code_for(status: u16) -> Option<u8> {
+    match status {
+        404 => Some(EXIT_NOT_FOUND),
+        408 => Some(EXIT_TIMEOUT),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_for_delivery_failures() {
+        assert_eq!(exit_code_for(404), Some(EXIT_NOT_FOUND));
+        assert_eq!(exit_code_for(408), Some(EXIT_TIMEOUT));
+        assert_eq!(exit_code_for(400), None);
+    }
+}
@@ -0,0 +1,257 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use time::OffsetDateTime;
+use time::format_description::well_known::Iso8601;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader, Lines};
+use tokio::time::Instant;
+
+use crate::args::{BinaryEncoding, ReplayArgs};
+use crate::client::ServerClient;
+use crate::output::OutputWriter;
+use crate::script_api::WsEvent;
+use crate::ws_client::EventHandler;
+
+/// Appends received messages to a file, one JSON line per message holding the
+/// event the transform sees, including the time it arrived.
+pub struct Recorder {
+    file: std::fs::File,
+    encoding: BinaryEncoding,
+}
+
+/// A recorded event. Binary events note their encoding, so they replay the
+/// same under any `--binary-encoding`.
+#[derive(Serialize)]
+struct RecordedEvent<'a> {
+    #[serde(flatten)]
+    event: &'a WsEvent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<BinaryEncoding>,
+}
+
+impl Recorder {
+    pub fn open(path: &Path, encoding: BinaryEncoding) -> Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open recording {}", path.display()))?;
+        Ok(Self { file, encoding })
+    }
+
+    pub fn record(&mut self, event: &WsEvent) -> Result<()> {
+        let recorded = RecordedEvent {
+            event,
+            encoding: event.binary.is_some().then_some(self.encoding),
+        };
+        let mut line = serde_json::to_vec(&recorded)?;
+        line.push(b'\n');
+        self.file
+            .write_all(&line)
+            .context("Failed to write recording")
+    }
+}
+
+/// Feeds a recording through the transform and outputs, like messages
+/// received by `sub`. There is no publisher to answer, so every message is
+/// handled like a shot message.
+pub async fn feed(
+    args: &ReplayArgs,
+    handler: &EventHandler<'_>,
+    writer: &mut OutputWriter,
+) -> Result<()> {
+    let mut recording = Recording::open(&args.recording, args.sub.binary_encoding).await?;
+    let mut pacer = Pacer::new(args);
+    let mut count = 0;
+    while let Some(event) = recording.next().await? {
+        pacer.wait(&event).await;
+        handler.handle(&event, false, writer).await?;
+        count += 1;
+    }
+    tracing::info!(
+        "Replayed {} message(s) from {}",
+        count,
+        args.recording.display()
+    );
+    Ok(())
+}
+
+/// Publishes a recording to `--id` on the server. Binary messages keep their
+/// bytes; undelivered messages are logged and fail the run at the end.
+pub async fn publish(server: &ServerClient, args: &ReplayArgs) -> Result<ExitCode> {
+    let sub = crate::config::single(&args.sub)?;
+    let url = server.http_url(&[&sub.mode.to_string(), "pub"], &[("id", &sub.id)]);
+    let mut recording = Recording::open(&args.recording, args.sub.binary_encoding).await?;
+    let mut pacer = Pacer::new(args);
+    let (mut sent, mut failed) = (0, 0);
+    while let Some(event) = recording.next().await? {
+        pacer.wait(&event).await;
+        let (content_type, body) = match (&event.raw, &event.text) {
+            (Some(raw), _) => ("application/octet-stream", raw.clone()),
+            (None, text) => ("text/plain", text.clone().unwrap_or_default().into_bytes()),
+        };
+        let resp = server
+            .post(url.clone())
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(body)
+            .send()
+            .await
+            .context("Failed to publish")?;
+        let status = resp.status();
+        if status.is_success() {
+            sent += 1;
+        } else {
+            failed += 1;
+            let message = resp.text().await.unwrap_or_default();
+            tracing::warn!(
+                "Line {}: publish failed ({}): {}",
+                recording.line_no,
+                status,
+                message
+            );
+        }
+    }
+    tracing::info!("Published {} message(s), {} failed", sent, failed);
+    Ok(if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+/// Events read one line at a time, so long recordings are not loaded whole.
+struct Recording {
+    path: PathBuf,
+    lines: Lines<Box<dyn AsyncBufRead + Unpin + Send>>,
+    line_no: usize,
+    encoding: BinaryEncoding,
+}
+
+impl Recording {
+    async fn open(path: &Path, encoding: BinaryEncoding) -> Result<Self> {
+        let reader: Box<dyn AsyncBufRead + Unpin + Send> = if path == Path::new("-") {
+            Box::new(BufReader::new(tokio::io::stdin()))
+        } else {
+            let file = tokio::fs::File::open(path)
+                .await
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            Box::new(BufReader::new(file))
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            lines: reader.lines(),
+            line_no: 0,
+            encoding,
+        })
+    }
+
+    async fn next(&mut self) -> Result<Option<WsEvent>> {
+        loop {
+            let Some(line) = self
+                .lines
+                .next_line()
+                .await
+                .with_context(|| format!("Failed to read {}", self.path.display()))?
+            else {
+                return Ok(None);
+            };
+            self.line_no += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return WsEvent::from_json_line(&line, self.encoding)
+                .map(Some)
+                .with_context(|| {
+                    format!("{}:{}: invalid event", self.path.display(), self.line_no)
+                });
+        }
+    }
+}
+
+/// Holds each event back until its recorded offset from the first event,
+/// divided by the speed, has passed. Does nothing without `--realtime`.
+struct Pacer {
+    speed: Option<f64>,
+    start: Option<(Instant, OffsetDateTime)>,
+}
+
+impl Pacer {
+    fn new(args: &ReplayArgs) -> Self {
+        Self {
+            speed: args.realtime.then_some(args.speed),
+            start: None,
+        }
+    }
+
+    async fn wait(&mut self, event: &WsEvent) {
+        let Some(speed) = self.speed else {
+            return;
+        };
+        let Ok(at) = OffsetDateTime::parse(&event.timestamp, &Iso8601::DEFAULT) else {
+            tracing::debug!("Unparseable timestamp '{}', not pacing", event.timestamp);
+            return;
+        };
+        let Some((started, first)) = self.start else {
+            self.start = Some((Instant::now(), at));
+            return;
+        };
+        if let Some(delay) = offset(first, at, speed) {
+            tokio::time::sleep_until(started + delay).await;
+        }
+    }
+}
+
+/// Playback offset of an event recorded at `at`; `None` if it is not after
+/// the first one (recordings can be concatenated or hand-edited).
+fn offset(first: OffsetDateTime, at: OffsetDateTime, speed: f64) -> Option<Duration> {
+    let elapsed = (at - first).as_seconds_f64();
+    (elapsed > 0.0).then(|| Duration::from_secs_f64(elapsed / speed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn recordings_read_back_as_received() {
+        let path = std::env::temp_dir().join("notir_test_recording.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let text = WsEvent::from_text("hi".to_string(), "broad", "u1");
+        let binary = WsEvent::from_binary(&[0xca, 0xfe], "single", "u1", BinaryEncoding::Hex);
+        let mut recorder = Recorder::open(&path, BinaryEncoding::Hex).unwrap();
+        recorder.record(&text).unwrap();
+        recorder.record(&binary).unwrap();
+        drop(recorder);
+
+        let mut recording = Recording::open(&path, BinaryEncoding::Hex).await.unwrap();
+        let first = recording.next().await.unwrap().unwrap();
+        assert_eq!(first.text.as_deref(), Some("hi"));
+        assert_eq!(first.source, "broad");
+        assert_eq!(first.timestamp, text.timestamp);
+        let second = recording.next().await.unwrap().unwrap();
+        assert_eq!(second.raw.as_deref(), Some(&[0xca, 0xfe][..]));
+        assert_eq!(second.binary.as_deref(), Some("cafe"));
+        assert!(recording.next().await.unwrap().is_none());
+
+        // Recorded with base64, replayed with hex.
+        let base64 = WsEvent::from_binary(&[0xca, 0xfe], "single", "u1", BinaryEncoding::Base64);
+        let mut recorder = Recorder::open(&path, BinaryEncoding::Base64).unwrap();
+        recorder.record(&base64).unwrap();
+        drop(recorder);
+        let mut recording = Recording::open(&path, BinaryEncoding::Hex).await.unwrap();
+        for _ in 0..2 {
+            recording.next().await.unwrap().unwrap();
+        }
+        let third = recording.next().await.unwrap().unwrap();
+        assert_eq!(third.raw.as_deref(), Some(&[0xca, 0xfe][..]));
+        assert_eq!(third.binary.as_deref(), Some("cafe"));
+
+        let first = OffsetDateTime::parse(&text.timestamp, &Iso8601::DEFAULT).unwrap();
+        let later = first + time::Duration::seconds(3);
+        assert_eq!(offset(first, later, 2.0), Some(Duration::from_millis(1500)));
+        assert_eq!(offset(later, first, 1.0), None);
+    }
+}
@@ -0,0 +1,553 @@
+use anyhow::{Context, Result, bail};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+
+use crate::args::RotateEvery;
+
+/// How append-mode output files are named, rotated and pruned.
+#[derive(Debug, Clone)]
+pub struct RotationConfig {
+    /// File name template; `{id}`, `{date}` and `{hour}` are substituted (UTC).
+    pub template: String,
+    /// Start a new file once the current one would exceed this many bytes.
+    pub max_size: Option<u64>,
+    /// Start a new file when the hour or day changes.
+    pub every: Option<RotateEvery>,
+    /// Rotated files to keep (0 = keep all).
+    pub max_files: usize,
+    /// Gzip files once they are rotated out.
+    pub compress: bool,
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        Self {
+            template: "{id}.txt".to_string(),
+            max_size: None,
+            every: None,
+            max_files: 0,
+            compress: false,
+        }
+    }
+}
+
+impl RotationConfig {
+    pub fn validate(&self) -> Result<()> {
+        let sample = render_template(&self.template, "id", OffsetDateTime::UNIX_EPOCH);
+        if sample.is_empty() || sample.contains(['/', '\\']) || sample == "." || sample == ".." {
+            bail!(
+                "--file-name must be a plain file name, got '{}'",
+                self.template
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Substitutes `{id}`, `{date}` (YYYY-MM-DD) and `{hour}` (HH) in `template`.
+pub fn render_template(template: &str, id: &str, now: OffsetDateTime) -> String {
+    template
+        .replace("{id}", id)
+        .replace("{date}", &date_string(now))
+        .replace("{hour}", &format!("{:02}", now.hour()))
+}
+
+fn date_string(t: OffsetDateTime) -> String {
+    format!("{:04}-{:02}-{:02}", t.year(), t.month() as u8, t.day())
+}
+
+/// Identifies the rotation period `t` falls in; empty without time rotation.
+pub fn period_key(every: Option<RotateEvery>, t: OffsetDateTime) -> String {
+    match every {
+        None => String::new(),
+        Some(RotateEvery::Daily) => date_string(t),
+        Some(RotateEvery::Hourly) => format!("{}T{:02}", date_string(t), t.hour()),
+    }
+}
+
+/// An append-only output file that rotates by size and/or time.
+pub struct RotatingFile {
+    dir: PathBuf,
+    id: String,
+    config: RotationConfig,
+    file: std::fs::File,
+    path: PathBuf,
+    size: u64,
+    period: String,
+}
+
+impl RotatingFile {
+    pub fn open(dir: &Path, id: &str, config: RotationConfig) -> Result<Self> {
+        Self::open_at(dir, id, config, OffsetDateTime::now_utc())
+    }
+
+    fn open_at(dir: &Path, id: &str, config: RotationConfig, now: OffsetDateTime) -> Result<Self> {
+        let path = dir.join(render_template(&config.template, id, now));
+        let (file, size, modified) = open_append(&path)?;
+        // A file left over from an earlier period is rotated on the first write.
+        let period = period_key(config.every, modified.unwrap_or(now));
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            id: id.to_string(),
+            config,
+            file,
+            path,
+            size,
+            period,
+        })
+    }
+
+    pub fn write_line(&mut self, msg: &str) -> Result<()> {
+        self.write_line_at(msg, OffsetDateTime::now_utc())
+    }
+
+    /// Appends `data` as-is, without a trailing newline.
+    pub fn write_raw(&mut self, data: &[u8]) -> Result<()> {
+        self.write_at(data, false, OffsetDateTime::now_utc())
+    }
+
+    fn write_line_at(&mut self, msg: &str, now: OffsetDateTime) -> Result<()> {
+        self.write_at(msg.as_bytes(), true, now)
+    }
+
+    fn write_at(&mut self, data: &[u8], newline: bool, now: OffsetDateTime) -> Result<()> {
+        let len = data.len() as u64 + newline as u64;
+        if self.needs_rotation(len, now) {
+            self.rotate(now)?;
+        }
+        self.file.write_all(data)?;
+        if newline {
+            self.file.write_all(b"\n")?;
+        }
+        self.size += len;
+        Ok(())
+    }
+
+    fn needs_rotation(&self, len: u64, now: OffsetDateTime) -> bool {
+        if self.config.every.is_some() && period_key(self.config.every, now) != self.period {
+            return true;
+        }
+        matches!(self.config.max_size, Some(max) if self.size > 0 && self.size + len > max)
+    }
+
+    fn rotate(&mut self, now: OffsetDateTime) -> Result<()> {
+        self.file.flush()?;
+        let next = self
+            .dir
+            .join(render_template(&self.config.template, &self.id, now));
+        // Templates without a changing part reuse the name; move the old file aside.
+        let rotated = if next == self.path {
+            let sealed = sealed_path(&self.path, now);
+            std::fs::rename(&self.path, &sealed)
+                .with_context(|| format!("Failed to rotate {}", self.path.display()))?;
+            sealed
+        } else {
+            self.path.clone()
+        };
+
+        let (file, size, _) = open_append(&next)?;
+        self.file = file;
+        self.path = next;
+        self.size = size;
+        self.period = period_key(self.config.every, now);
+        tracing::info!("Rotated {} -> {}", rotated.display(), self.path.display());
+
+        let dir = self.dir.clone();
+        let active = self.path.clone();
+        let pattern = name_pattern(&self.config.template, &self.id);
+        let max_files = self.config.max_files;
+        if self.config.compress {
+            // Compression can take a while; keep it off the message path.
+            std::thread::spawn(move || {
+                if let Err(e) = compress_file(&rotated) {
+                    tracing::warn!("Failed to compress {}: {:#}", rotated.display(), e);
+                }
+                prune_rotated(&dir, &active, &pattern, max_files);
+            });
+        } else {
+            prune_rotated(&dir, &active, &pattern, max_files);
+        }
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<(std::fs::File, u64, Option<OffsetDateTime>)> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let meta = file.metadata()?;
+    let modified = if meta.len() > 0 {
+        meta.modified().ok().map(OffsetDateTime::from)
+    } else {
+        None
+    };
+    Ok((file, meta.len(), modified))
+}
+
+/// `u1.txt` -> `u1.20261018T130000.txt`, with `-N` appended on collision.
+fn sealed_path(path: &Path, now: OffsetDateTime) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), format!(".{}", ext)),
+        _ => (name.clone(), String::new()),
+    };
+    let stamp = format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}",
+        now.year(),
+        now.month() as u8,
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    );
+    let mut n = 0;
+    loop {
+        let suffix = if n == 0 {
+            String::new()
+        } else {
+            format!("-{}", n)
+        };
+        let candidate = path.with_file_name(format!("{}.{}{}{}", stem, stamp, suffix, ext));
+        if !candidate.exists() && !gz_path(&candidate).exists() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+/// One character of a name the template produces: fixed, or a digit where
+/// `{date}` or `{hour}` goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NameChar {
+    Exact(char),
+    Digit,
+}
+
+/// Every name this template produces for `id`, as one fixed-length pattern.
+fn name_pattern(template: &str, id: &str) -> Vec<NameChar> {
+    let digits = |shape: &str| {
+        shape
+            .chars()
+            .map(|c| match c {
+                'D' => NameChar::Digit,
+                c => NameChar::Exact(c),
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut pattern = Vec::new();
+    let mut rest = template;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("{id}") {
+            pattern.extend(id.chars().map(NameChar::Exact));
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{date}") {
+            pattern.extend(digits("DDDD-DD-DD"));
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{hour}") {
+            pattern.extend(digits("DD"));
+            rest = after;
+        } else {
+            pattern.push(NameChar::Exact(c));
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    pattern
+}
+
+fn matches(pattern: &[NameChar], name: &[char]) -> bool {
+    pattern.len() == name.len()
+        && pattern.iter().zip(name).all(|(p, c)| match p {
+            NameChar::Exact(e) => e == c,
+            NameChar::Digit => c.is_ascii_digit(),
+        })
+}
+
+/// Whether `name` is an output file of this template, for any period, or a
+/// copy sealed by `sealed_path` (`{stem}.{YYYYMMDDTHHMMSS}[-N]{.ext}`),
+/// optionally gzipped. Other files next to them are never matched.
+fn is_rotated_name(pattern: &[NameChar], name: &str) -> bool {
+    let name: Vec<char> = name.strip_suffix(".gz").unwrap_or(name).chars().collect();
+    if matches(pattern, &name) {
+        return true;
+    }
+    let split = pattern
+        .iter()
+        .rposition(|c| *c == NameChar::Exact('.'))
+        .filter(|&i| i > 0)
+        .unwrap_or(pattern.len());
+    let (stem, ext) = pattern.split_at(split);
+    if name.len() < stem.len() + SEAL_STAMP_LEN + ext.len() {
+        return false;
+    }
+    let (head, rest) = name.split_at(stem.len());
+    let (seal, tail) = rest.split_at(rest.len() - ext.len());
+    matches(stem, head) && matches(ext, tail) && is_seal(seal)
+}
+
+/// Length of `.YYYYMMDDTHHMMSS`.
+const SEAL_STAMP_LEN: usize = 16;
+
+fn is_seal(seal: &[char]) -> bool {
+    let (stamp, counter) = seal.split_at(SEAL_STAMP_LEN);
+    let stamp_ok = stamp.iter().enumerate().all(|(i, c)| match i {
+        0 => *c == '.',
+        9 => *c == 'T',
+        _ => c.is_ascii_digit(),
+    });
+    let counter_ok = match counter.split_first() {
+        None => true,
+        Some((dash, n)) => *dash == '-' && !n.is_empty() && n.iter().all(char::is_ascii_digit),
+    };
+    stamp_ok && counter_ok
+}
+
+/// Deletes the oldest rotated files beyond `max_files`.
+fn prune_rotated(dir: &Path, active: &Path, pattern: &[NameChar], max_files: usize) {
+    if max_files == 0 {
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut rotated: Vec<(std::time::SystemTime, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path() != active)
+        .filter(|entry| is_rotated_name(pattern, &entry.file_name().to_string_lossy()))
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok()?;
+            if !meta.is_file() {
+                return None;
+            }
+            Some((meta.modified().ok()?, entry.path()))
+        })
+        .collect();
+    rotated.sort_by(|a, b| b.cmp(a));
+    for (_, path) in rotated.into_iter().skip(max_files) {
+        match std::fs::remove_file(&path) {
+            Ok(()) => tracing::info!("Removed old output file {}", path.display()),
+            Err(e) => tracing::warn!("Failed to remove {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Gzips `path` to `path.gz` and removes the original.
+fn compress_file(path: &Path) -> Result<()> {
+    let target = gz_path(path);
+    let tmp = target.with_extension("gz.tmp");
+    {
+        let mut input = std::fs::File::open(path)?;
+        let output = std::fs::File::create(&tmp)?;
+        let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+        std::io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?.sync_all()?;
+    }
+    std::fs::rename(&tmp, &target)?;
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Whether `name` is a `period_key`: `YYYY-MM-DD` or `YYYY-MM-DDTHH`.
+fn is_period_key(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    let digits = |range: std::ops::Range<usize>| bytes[range].iter().all(u8::is_ascii_digit);
+    let date = bytes.len() >= 10
+        && digits(0..4)
+        && bytes[4] == b'-'
+        && digits(5..7)
+        && bytes[7] == b'-'
+        && digits(8..10);
+    match bytes.len() {
+        10 => date,
+        13 => date && bytes[10] == b'T' && digits(11..13),
+        _ => false,
+    }
+}
+
+/// Deletes the oldest period directories under `root` beyond `max_dirs`.
+/// Directories not named after a period are left alone.
+pub fn prune_period_dirs(root: &Path, active: &Path, max_dirs: usize) {
+    if max_dirs == 0 {
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(root) else {
+        return;
+    };
+    // Period names (YYYY-MM-DD[THH]) sort chronologically.
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() && path != active)
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(is_period_key)
+        })
+        .collect();
+    dirs.sort();
+    let excess = dirs.len().saturating_sub(max_dirs);
+    for dir in dirs.into_iter().take(excess) {
+        match std::fs::remove_dir_all(&dir) {
+            Ok(()) => tracing::info!("Removed old output directory {}", dir.display()),
+            Err(e) => tracing::warn!("Failed to remove {}: {}", dir.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn template_substitutes_id_and_date() {
+        let t = datetime!(2026-10-18 07:30:00 UTC);
+        assert_eq!(
+            render_template("{id}-{date}-{hour}.log", "u1", t),
+            "u1-2026-10-18-07.log"
+        );
+        assert_eq!(period_key(Some(RotateEvery::Hourly), t), "2026-10-18T07");
+        assert!(
+            RotationConfig {
+                template: "../{id}.txt".into(),
+                ..Default::default()
+            }
+            .validate()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn rotates_by_size_and_prunes() {
+        let dir = temp_dir("notir_test_rotate_size");
+        let config = RotationConfig {
+            max_size: Some(10),
+            max_files: 1,
+            ..Default::default()
+        };
+        let t = datetime!(2026-10-18 07:30:00 UTC);
+        // Neighbours that only look like rotated files must survive pruning.
+        for neighbour in ["u1.other.txt", "u1.20261018T000000.txt.bak", "u10.txt"] {
+            std::fs::write(dir.join(neighbour), "keep\n").unwrap();
+        }
+        let mut file = RotatingFile::open_at(&dir, "u1", config, t).unwrap();
+        file.write_line_at("12345678", t).unwrap();
+        file.write_line_at("abc", t).unwrap();
+        file.write_line_at("second", datetime!(2026-10-18 07:31:00 UTC))
+            .unwrap();
+        file.write_line_at("third", datetime!(2026-10-18 07:32:00 UTC))
+            .unwrap();
+        file.write_line_at("fourth", datetime!(2026-10-18 07:33:00 UTC))
+            .unwrap();
+        assert_eq!(
+            names(&dir),
+            vec![
+                "u1.20261018T000000.txt.bak".to_string(),
+                "u1.20261018T073300.txt".to_string(),
+                "u1.other.txt".to_string(),
+                "u1.txt".to_string(),
+                "u10.txt".to_string(),
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("u1.txt")).unwrap(),
+            "fourth\n"
+        );
+    }
+
+    #[test]
+    fn rotates_when_the_day_changes() {
+        let dir = temp_dir("notir_test_rotate_daily");
+        let config = RotationConfig {
+            template: "{id}-{date}.log".into(),
+            every: Some(RotateEvery::Daily),
+            ..Default::default()
+        };
+        let mut file =
+            RotatingFile::open_at(&dir, "u1", config, datetime!(2026-10-18 23:59:00 UTC)).unwrap();
+        file.write_line_at("a", datetime!(2026-10-18 23:59:30 UTC))
+            .unwrap();
+        file.write_line_at("b", datetime!(2026-10-19 00:00:01 UTC))
+            .unwrap();
+        assert_eq!(
+            names(&dir),
+            vec![
+                "u1-2026-10-18.log".to_string(),
+                "u1-2026-10-19.log".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn prune_keeps_non_period_dirs() {
+        let root = temp_dir("notir_test_prune_period_dirs");
+        for name in [
+            "2024-03-01",
+            "2024-03-02T05",
+            "2024-03-03",
+            "archive",
+            "2024-03",
+        ] {
+            std::fs::create_dir(root.join(name)).unwrap();
+        }
+        prune_period_dirs(&root, &root.join("2024-03-03"), 1);
+        assert_eq!(
+            names(&root),
+            vec!["2024-03", "2024-03-02T05", "2024-03-03", "archive"]
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn compress_replaces_file_with_gzip() {
+        let dir = temp_dir("notir_test_rotate_gzip");
+        let path = dir.join("u1.20261018T000000.txt");
+        std::fs::write(&path, "hello\n").unwrap();
+        compress_file(&path).unwrap();
+        assert_eq!(names(&dir), vec!["u1.20261018T000000.txt.gz".to_string()]);
+
+        let mut decoded = String::new();
+        std::io::Read::read_to_string(
+            &mut flate2::read::GzDecoder::new(std::fs::File::open(gz_path(&path)).unwrap()),
+            &mut decoded,
+        )
+        .unwrap();
+        assert_eq!(decoded, "hello\n");
+        let plain = name_pattern("{id}.txt", "u1");
+        assert!(is_rotated_name(&plain, "u1.20261018T000000.txt.gz"));
+        assert!(is_rotated_name(&plain, "u1.20261018T000000-2.txt"));
+        assert!(!is_rotated_name(&plain, "u1.errors.txt"));
+        assert!(!is_rotated_name(&plain, "u10.txt"));
+        let dated = name_pattern("{id}-{date}.log", "u1");
+        assert!(is_rotated_name(&dated, "u1-2026-10-17.log.gz"));
+        assert!(is_rotated_name(&dated, "u1-2026-10-17.20261017T120000.log"));
+        assert!(!is_rotated_name(&dated, "u1-errors.log"));
+    }
+}
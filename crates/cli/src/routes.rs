@@ -0,0 +1,108 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::exec::ExecHook;
+use crate::webhook::Webhook;
+
+/// One item returned by `transform`: where it goes (`None` for the default
+/// output) and what to write (`None` for the original message).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Routed {
+    pub target: Option<String>,
+    pub data: Option<String>,
+}
+
+/// A parsed routing target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target<'a> {
+    /// The output configured with `--output`, plus `--exec`.
+    Default,
+    Stdout,
+    /// `<output-dir>/<id>.<name>.txt`, rotated like the main output file.
+    File(&'a str),
+    /// A command declared with `--route-exec`.
+    Exec(&'a str),
+    /// A URL declared with `--route-webhook`.
+    Webhook(&'a str),
+}
+
+impl<'a> Target<'a> {
+    pub fn parse(target: &'a str) -> Result<Self, String> {
+        let (kind, name) = match target.split_once(':') {
+            Some((kind, name)) => (kind, Some(name)),
+            None => (target, None),
+        };
+        let named = |make: fn(&'a str) -> Target<'a>| match name {
+            Some(name) => validate_name(name).map(|()| make(name)),
+            None => Err(format!(
+                "target '{}' needs a name, e.g. '{}:alerts'",
+                target, kind
+            )),
+        };
+        match (kind, name) {
+            ("default", None) => Ok(Target::Default),
+            ("stdout", None) => Ok(Target::Stdout),
+            ("file", _) => named(Target::File),
+            ("exec", _) => named(Target::Exec),
+            ("webhook", _) => named(Target::Webhook),
+            _ => Err(format!(
+                "unknown target '{}' (expected default, stdout, file:NAME, exec:NAME or webhook:NAME)",
+                target
+            )),
+        }
+    }
+}
+
+/// Route names double as file names, so keep them to a safe character set.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid route name '{}': use letters, digits, '-', '_' and '.'",
+            name
+        ))
+    }
+}
+
+/// Commands and webhooks that `exec:NAME` and `webhook:NAME` targets refer to.
+#[derive(Default)]
+pub struct Routes {
+    pub execs: HashMap<String, ExecHook>,
+    pub webhooks: HashMap<String, Webhook>,
+}
+
+impl Routes {
+    /// Waits for queued commands and webhook deliveries; see [`Webhook::finish`].
+    pub async fn finish(self, interrupted: bool) {
+        for (_, exec) in self.execs {
+            exec.finish().await;
+        }
+        for (_, webhook) in self.webhooks {
+            webhook.finish(interrupted).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_parse() {
+        assert_eq!(Target::parse("default"), Ok(Target::Default));
+        assert_eq!(Target::parse("stdout"), Ok(Target::Stdout));
+        assert_eq!(Target::parse("file:alerts"), Ok(Target::File("alerts")));
+        assert_eq!(Target::parse("exec:notify"), Ok(Target::Exec("notify")));
+        assert_eq!(Target::parse("webhook:slack"), Ok(Target::Webhook("slack")));
+        assert!(Target::parse("file").is_err());
+        assert!(Target::parse("file:../etc/passwd").is_err());
+        assert!(Target::parse("stdout:x").is_err());
+        assert!(Target::parse("email:ops").is_err());
+    }
+}
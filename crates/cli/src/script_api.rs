@@ -1,11 +1,15 @@
-use serde::Serialize;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::args::BinaryEncoding;
+use crate::util::{from_base64, from_hex, to_base64, to_hex};
 
 /// Event object passed into the user's JS `transform()` function.
 #[derive(Debug, Serialize)]
 pub struct WsEvent {
     /// UTF-8 text content (null if binary message)
     pub text: Option<String>,
-    /// Hex-encoded binary content (null if text message)
+    /// Hex (default) or base64 encoded binary content (null if text message)
     pub binary: Option<String>,
     /// ISO 8601 timestamp of when the message was received
     pub timestamp: String,
@@ -14,6 +18,24 @@ pub struct WsEvent {
     pub msg_type: &'static str,
     /// "single" or "broad"
     pub source: String,
+    /// Channel id the message arrived on
+    pub id: String,
+    /// Raw payload of a binary message, for outputs that write bytes as-is
+    #[serde(skip)]
+    pub raw: Option<Vec<u8>>,
+}
+
+/// A `WsEvent` as stored in a JSON line. Every field is optional and extra
+/// fields (such as `result`/`error` from `--format jsonl`) are ignored.
+#[derive(Debug, Deserialize)]
+struct EventLine {
+    text: Option<String>,
+    binary: Option<String>,
+    timestamp: Option<String>,
+    source: Option<String>,
+    id: Option<String>,
+    /// How `binary` is encoded, as `sub --record` notes it.
+    encoding: Option<BinaryEncoding>,
 }
 
 fn now_iso8601() -> String {
@@ -22,34 +44,62 @@ fn now_iso8601() -> String {
         .unwrap_or_default()
 }
 
-fn to_hex(data: &[u8]) -> String {
-    const HEX: &[u8; 16] = b"0123456789abcdef";
-    let mut s = String::with_capacity(data.len() * 2);
-    for &b in data {
-        s.push(HEX[(b >> 4) as usize] as char);
-        s.push(HEX[(b & 0x0f) as usize] as char);
+impl WsEvent {
+    /// Rebuilds an event from a JSON line such as `--format jsonl` writes.
+    /// `binary` is decoded with the line's `encoding` if it has one, else with
+    /// `encoding`, and handed on in `encoding`; a missing timestamp is now.
+    pub fn from_json_line(line: &str, encoding: BinaryEncoding) -> Result<Self> {
+        let line: EventLine = serde_json::from_str(line)?;
+        let source = line.source.unwrap_or_else(|| "single".to_string());
+        let id = line.id.unwrap_or_default();
+        let timestamp = line.timestamp.unwrap_or_else(now_iso8601);
+        let event = match (line.text, line.binary) {
+            (_, Some(binary)) => {
+                let recorded = line.encoding.unwrap_or(encoding);
+                let raw = match recorded {
+                    BinaryEncoding::Hex => from_hex(&binary),
+                    BinaryEncoding::Base64 => from_base64(&binary),
+                }
+                .ok_or_else(|| {
+                    let name = match recorded {
+                        BinaryEncoding::Hex => "hex",
+                        BinaryEncoding::Base64 => "base64",
+                    };
+                    anyhow::anyhow!("'binary' is not valid {}", name)
+                })?;
+                Self::from_binary(&raw, &source, &id, encoding)
+            }
+            (Some(text), None) => Self::from_text(text, &source, &id),
+            (None, None) => anyhow::bail!("event has neither 'text' nor 'binary'"),
+        };
+        Ok(Self { timestamp, ..event })
     }
-    s
-}
 
-impl WsEvent {
-    pub fn from_text(text: String, source: &str) -> Self {
+    pub fn from_text(text: String, source: &str, id: &str) -> Self {
         Self {
             text: Some(text),
             binary: None,
             timestamp: now_iso8601(),
             msg_type: "text",
             source: source.to_string(),
+            id: id.to_string(),
+            raw: None,
         }
     }
 
-    pub fn from_binary(data: &[u8], source: &str) -> Self {
+    pub fn from_binary(data: &[u8], source: &str, id: &str, encoding: BinaryEncoding) -> Self {
+        let encoded = match encoding {
+            BinaryEncoding::Hex => to_hex(data),
+            BinaryEncoding::Base64 => to_base64(data),
+        };
         Self {
             text: None,
-            binary: Some(to_hex(data)),
+            binary: Some(encoded),
             timestamp: now_iso8601(),
             msg_type: "binary",
             source: source.to_string(),
+            id: id.to_string(),
+            raw: Some(data.to_vec()),
         }
     }
 
@@ -65,19 +115,242 @@ impl WsEvent {
 pub(crate) const PASSTHROUGH_SCRIPT: &str =
     "function transform(e) { return e.text !== null ? e.text : e.binary; }";
 
+/// Defines the global `state` key-value store. Values must be JSON-serializable
+/// to survive a restart with `--state-file`; `ttl` is in seconds.
+pub(crate) const STATE_PRELUDE: &str = r#"
+globalThis.state = (function () {
+  var data = new Map();
+  function live(key) {
+    var item = data.get(String(key));
+    if (item === undefined) return undefined;
+    if (item.e !== null && item.e <= Date.now()) {
+      data.delete(String(key));
+      return undefined;
+    }
+    return item;
+  }
+  function expiry(ttl) {
+    return ttl > 0 ? Date.now() + ttl * 1000 : null;
+  }
+  return {
+    get: function (key, fallback) {
+      var item = live(key);
+      return item === undefined ? fallback : item.v;
+    },
+    set: function (key, value, ttl) {
+      data.set(String(key), { v: value, e: expiry(ttl) });
+      return value;
+    },
+    has: function (key) {
+      return live(key) !== undefined;
+    },
+    delete: function (key) {
+      return data.delete(String(key));
+    },
+    incr: function (key, by, ttl) {
+      var item = live(key);
+      var value = (item === undefined ? 0 : Number(item.v)) + (by === undefined ? 1 : by);
+      data.set(String(key), { v: value, e: item === undefined ? expiry(ttl) : item.e });
+      return value;
+    },
+    keys: function () {
+      return Array.from(data.keys()).filter(function (key) { return live(key) !== undefined; });
+    },
+    clear: function () {
+      data.clear();
+    },
+    __dump: function () {
+      var out = [];
+      data.forEach(function (item, key) {
+        if (item.e === null || item.e > Date.now()) out.push([key, item]);
+      });
+      return JSON.stringify(out);
+    },
+    __load: function (json) {
+      JSON.parse(json).forEach(function (entry) { data.set(entry[0], entry[1]); });
+    }
+  };
+})();
+"#;
+
+/// Timers, `fetch` and the bookkeeping `JsEngine` uses to drive async
+/// `transform`/`reply` calls. Requests queued by `fetch` are performed by the
+/// host, which settles them through `__loop.settle`.
+pub(crate) const EVENT_LOOP_PRELUDE: &str = r#"
+globalThis.__loop = (function () {
+  var timers = new Map();
+  var nextTimer = 1;
+  var requests = new Map();
+  var outbox = [];
+  var nextRequest = 1;
+  var loop = { outcome: null };
+
+  globalThis.setTimeout = function (fn, ms) {
+    var args = Array.prototype.slice.call(arguments, 2);
+    var id = nextTimer++;
+    timers.set(id, { fn: fn, args: args, due: Date.now() + Math.max(0, Number(ms) || 0) });
+    return id;
+  };
+  globalThis.clearTimeout = function (id) {
+    timers.delete(id);
+  };
+
+  globalThis.fetch = function (url, init) {
+    init = init || {};
+    return new Promise(function (resolve, reject) {
+      var id = nextRequest++;
+      var headers = {};
+      Object.keys(init.headers || {}).forEach(function (name) {
+        headers[name] = String(init.headers[name]);
+      });
+      requests.set(id, { url: String(url), resolve: resolve, reject: reject });
+      outbox.push({
+        id: id,
+        url: String(url),
+        method: String(init.method || "GET").toUpperCase(),
+        headers: headers,
+        body: init.body == null ? null : String(init.body)
+      });
+    });
+  };
+
+  function response(url, status, headers, body) {
+    return {
+      ok: status >= 200 && status < 300,
+      status: status,
+      url: url,
+      headers: {
+        get: function (name) {
+          var value = headers[String(name).toLowerCase()];
+          return value === undefined ? null : value;
+        }
+      },
+      text: function () { return Promise.resolve(body); },
+      json: function () { return Promise.resolve(body).then(JSON.parse); }
+    };
+  }
+
+  // Calls the script function; returns true when the outcome is already known.
+  loop.start = function (name, event) {
+    loop.outcome = null;
+    var result = globalThis[name](event);
+    if (result && typeof result.then === "function") {
+      result.then(
+        function (value) { loop.outcome = { value: value }; },
+        function (error) { loop.outcome = { error: String(error) }; }
+      );
+      return false;
+    }
+    loop.outcome = { value: result };
+    return true;
+  };
+  // Normalizes a transform result into {target, data} items (null target:
+  // default output; null data: the original message).
+  loop.routes = function (value) {
+    var items = Array.isArray(value) ? value : [value];
+    return items.map(function (item) {
+      if (item === null || item === undefined) return null;
+      if (typeof item === "string") return { target: null, data: item };
+      if (typeof item !== "object" || Array.isArray(item)) {
+        throw new TypeError("transform must return a string, null, {target, data} or an array of these");
+      }
+      if (item.data === null) return null;
+      var target = item.target === undefined ? null : String(item.target);
+      if (item.data === undefined) return { target: target, data: null };
+      return { target: target, data: typeof item.data === "string" ? item.data : JSON.stringify(item.data) };
+    }).filter(function (item) { return item !== null; });
+  };
+  // Runs due timers in order; returns how many fired.
+  loop.runTimers = function () {
+    var now = Date.now();
+    var due = [];
+    timers.forEach(function (timer, id) {
+      if (timer.due <= now) due.push([id, timer]);
+    });
+    due.sort(function (a, b) { return a[1].due - b[1].due || a[0] - b[0]; });
+    due.forEach(function (entry) {
+      if (timers.delete(entry[0])) entry[1].fn.apply(null, entry[1].args);
+    });
+    return due.length;
+  };
+  // Milliseconds until the next timer, or -1 when none is set.
+  loop.nextTimer = function () {
+    var next = -1;
+    timers.forEach(function (timer) {
+      if (next < 0 || timer.due < next) next = timer.due;
+    });
+    return next < 0 ? -1 : Math.max(0, next - Date.now());
+  };
+  loop.takeRequests = function () {
+    var taken = JSON.stringify(outbox);
+    outbox = [];
+    return taken;
+  };
+  loop.settle = function (id, error, status, headers, body) {
+    var request = requests.get(id);
+    if (request === undefined) return;
+    requests.delete(id);
+    if (error != null) request.reject(new TypeError("fetch failed: " + error));
+    else request.resolve(response(request.url, status, JSON.parse(headers), body));
+  };
+  // Drops whatever a finished (or timed out) call left behind.
+  loop.reset = function () {
+    timers.clear();
+    requests.clear();
+    outbox = [];
+    loop.outcome = null;
+  };
+  return loop;
+})();
+"#;
+
 #[cfg(test)]
 mod tests {
-    use super::WsEvent;
+    use super::*;
+
+    #[test]
+    fn events_round_trip_through_json_lines() {
+        let binary = WsEvent::from_binary(
+            &[0, 1, 0xfe, 0xff, 7],
+            "broad",
+            "u1",
+            BinaryEncoding::Base64,
+        );
+        let line = serde_json::to_string(&binary).unwrap();
+        let parsed = WsEvent::from_json_line(&line, BinaryEncoding::Base64).unwrap();
+        assert_eq!(parsed.raw.as_deref(), Some(&[0, 1, 0xfe, 0xff, 7][..]));
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), line);
+
+        let text =
+            WsEvent::from_json_line(r#"{"text":"hi","result":"x"}"#, BinaryEncoding::Hex).unwrap();
+        assert_eq!((text.msg_type, text.source.as_str()), ("text", "single"));
+        let hex = WsEvent::from_json_line(r#"{"binary":"dead"}"#, BinaryEncoding::Hex).unwrap();
+        assert_eq!(hex.raw.as_deref(), Some(&[0xde, 0xad][..]));
+        assert!(WsEvent::from_json_line(r#"{"binary":"xyz"}"#, BinaryEncoding::Hex).is_err());
+        assert!(WsEvent::from_json_line(r#"{}"#, BinaryEncoding::Hex).is_err());
+    }
 
     #[test]
     fn raw_output_prefers_text() {
-        let event = WsEvent::from_text("hello".to_string(), "single");
+        let event = WsEvent::from_text("hello".to_string(), "single", "u1");
         assert_eq!(event.raw_output(), "hello");
     }
 
     #[test]
     fn raw_output_uses_binary_hex() {
-        let event = WsEvent::from_binary(&[0xde, 0xad, 0xbe, 0xef], "broad");
+        let event = WsEvent::from_binary(
+            &[0xde, 0xad, 0xbe, 0xef],
+            "broad",
+            "u1",
+            BinaryEncoding::Hex,
+        );
         assert_eq!(event.raw_output(), "deadbeef");
     }
+
+    #[test]
+    fn binary_base64_keeps_raw_bytes() {
+        let event = WsEvent::from_binary(&[0xff, 0xfe], "single", "u1", BinaryEncoding::Base64);
+        assert_eq!(event.binary.as_deref(), Some("//4="));
+        assert_eq!(event.raw, Some(vec![0xff, 0xfe]));
+    }
 }
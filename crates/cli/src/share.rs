@@ -0,0 +1,355 @@
+use anyhow::{Context, Result, bail};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncReadExt;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::args::ShareArgs;
+use crate::client::ServerClient;
+
+/// Chunk size used by the web client as well; the server relays chunks as-is.
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// A local file that is about to be offered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedFile {
+    pub path: PathBuf,
+    /// Name shown to receivers; relative path inside a shared directory.
+    pub name: String,
+    pub size: u64,
+}
+
+/// An offer sent to the server that has not been answered yet.
+enum PendingOffer {
+    File(SharedFile),
+    Bundle(String, Vec<SharedFile>),
+}
+
+/// The file currently being streamed in answer to a `pull`.
+struct Upload {
+    file_id: String,
+    file: tokio::fs::File,
+}
+
+pub async fn run(server: &ServerClient, args: &ShareArgs) -> Result<()> {
+    let files = collect_files(&args.paths)?;
+    if files.is_empty() {
+        bail!("No files to share");
+    }
+
+    let url = server.ws_url(&["broad", "sub"], &[("id", &args.room)]);
+    tracing::info!("Connecting to {}", url);
+    let ws_stream = server
+        .connect(&url)
+        .await
+        .with_context(|| format!("Failed to connect to {}", url))?;
+    tracing::info!("Connected to {}", url);
+    let (mut write, mut read) = ws_stream.split();
+
+    let mut pending = VecDeque::new();
+    match &args.bundle {
+        Some(name) => {
+            let op = with_access(
+                json!({
+                    "op": "offer_bundle",
+                    "name": name,
+                    "files": files
+                        .iter()
+                        .map(|f| json!({"name": f.name, "size": f.size}))
+                        .collect::<Vec<_>>(),
+                }),
+                args,
+            );
+            write.send(Message::text(op.to_string())).await?;
+            pending.push_back(PendingOffer::Bundle(name.clone(), files));
+        }
+        None => {
+            for file in files {
+                let op = with_access(
+                    json!({
+                        "op": "offer",
+                        "name": file.name,
+                        "size": file.size,
+                        "mime": guess_mime(&file.name),
+                    }),
+                    args,
+                );
+                write.send(Message::text(op.to_string())).await?;
+                pending.push_back(PendingOffer::File(file));
+            }
+        }
+    }
+
+    // offer fileId -> display name; pull fileId (member ids for bundles) -> local path
+    let mut offers: HashMap<String, String> = HashMap::new();
+    let mut sources: HashMap<String, PathBuf> = HashMap::new();
+    let mut upload: Option<Upload> = None;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+
+    loop {
+        tokio::select! {
+            msg = read.next() => {
+                let control = match msg {
+                    Some(Ok(Message::Binary(data))) => match serde_json::from_slice::<Value>(&data) {
+                        Ok(control) => control,
+                        Err(_) => continue,
+                    },
+                    Some(Ok(Message::Close(frame))) => {
+                        tracing::info!("Server closed connection: {:?}", frame);
+                        break;
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e).context("WebSocket error"),
+                    None => break,
+                };
+                let op = control["op"].as_str().unwrap_or_default();
+                match op {
+                    "offer_ok" | "bundle_ok" | "error" if !pending.is_empty() => {
+                        // Offers are answered in the order they were sent.
+                        let offer = pending.pop_front().expect("checked non-empty");
+                        if op == "error" {
+                            tracing::error!(
+                                "Offer rejected: {}",
+                                control["message"].as_str().unwrap_or("unknown error")
+                            );
+                        } else {
+                            register_offer(&control, offer, server, &mut offers, &mut sources);
+                        }
+                    }
+                    "error" => {
+                        tracing::warn!(
+                            "Server error: {}",
+                            control["message"].as_str().unwrap_or("unknown error")
+                        );
+                    }
+                    "pull" => {
+                        let file_id = control["fileId"].as_str().unwrap_or_default().to_string();
+                        match sources.get(&file_id) {
+                            Some(path) => match tokio::fs::File::open(path).await {
+                                Ok(file) => {
+                                    tracing::info!("Streaming {} ({})", path.display(), file_id);
+                                    upload = Some(Upload { file_id, file });
+                                }
+                                Err(e) => {
+                                    tracing::error!("Failed to open {}: {}", path.display(), e);
+                                    write.send(Message::text(json!({"op": "abort"}).to_string())).await?;
+                                }
+                            },
+                            None => {
+                                tracing::warn!("Pull for unknown file {}", file_id);
+                                write.send(Message::text(json!({"op": "abort"}).to_string())).await?;
+                            }
+                        }
+                    }
+                    "cancel" => {
+                        // The server already released the transfer; just stop sending.
+                        if let Some(upload) = upload.take() {
+                            tracing::info!("Download of {} cancelled by receiver", upload.file_id);
+                        }
+                    }
+                    "offer_removed" => {
+                        let file_id = control["fileId"].as_str().unwrap_or_default();
+                        if let Some(name) = offers.remove(file_id) {
+                            tracing::info!(
+                                "Offer {} removed: {}",
+                                name,
+                                control["reason"].as_str().unwrap_or("unknown")
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            read_result = read_chunk(&mut upload, &mut buf), if upload.is_some() => {
+                match read_result {
+                    Ok(0) => {
+                        write.send(Message::text(json!({"op": "done"}).to_string())).await?;
+                        if let Some(upload) = upload.take() {
+                            tracing::info!("Finished streaming {}", upload.file_id);
+                        }
+                    }
+                    Ok(n) => {
+                        // `send` flushes each frame, so a slow receiver throttles reading here.
+                        write.send(Message::binary(buf[..n].to_vec())).await?;
+                    }
+                    Err(e) => {
+                        tracing::error!("Read error while streaming: {}", e);
+                        write.send(Message::text(json!({"op": "abort"}).to_string())).await?;
+                        upload = None;
+                    }
+                }
+            }
+        }
+
+        if pending.is_empty() && offers.is_empty() && upload.is_none() {
+            tracing::info!("No offers left, exiting");
+            break;
+        }
+    }
+
+    let _ = write.close().await;
+    Ok(())
+}
+
+async fn read_chunk(upload: &mut Option<Upload>, buf: &mut [u8]) -> std::io::Result<usize> {
+    match upload {
+        Some(upload) => upload.file.read(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn with_access(mut op: Value, args: &ShareArgs) -> Value {
+    if let Some(ttl) = args.ttl {
+        op["ttl"] = json!(ttl);
+    }
+    if let Some(password) = &args.password {
+        op["password"] = json!(password);
+    }
+    if let Some(max_downloads) = args.max_downloads {
+        op["maxDownloads"] = json!(max_downloads);
+    }
+    op
+}
+
+fn register_offer(
+    control: &Value,
+    offer: PendingOffer,
+    server: &ServerClient,
+    offers: &mut HashMap<String, String>,
+    sources: &mut HashMap<String, PathBuf>,
+) {
+    let Some(file_id) = control["fileId"].as_str() else {
+        return;
+    };
+    let name = match offer {
+        PendingOffer::File(file) => {
+            sources.insert(file_id.to_string(), file.path);
+            file.name
+        }
+        PendingOffer::Bundle(name, files) => {
+            let members = control["files"].as_array().cloned().unwrap_or_default();
+            for (member, file) in members.iter().zip(files) {
+                if let Some(member_id) = member["fileId"].as_str() {
+                    sources.insert(member_id.to_string(), file.path);
+                }
+            }
+            name
+        }
+    };
+    let link = server.http_url(&["files", "download", file_id], &[]);
+    println!("{}\t{}", name, link);
+    offers.insert(file_id.to_string(), name);
+}
+
+/// Expands the given paths into regular files. Files inside a directory are
+/// named by their path relative to the directory's parent, using `/`.
+pub fn collect_files(paths: &[PathBuf]) -> Result<Vec<SharedFile>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let meta = std::fs::metadata(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if meta.is_dir() {
+            let root = path.parent().unwrap_or(Path::new(""));
+            walk_dir(path, root, &mut files)?;
+        } else {
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string());
+            files.push(SharedFile {
+                path: path.clone(),
+                name,
+                size: meta.len(),
+            });
+        }
+    }
+    Ok(files)
+}
+
+fn walk_dir(dir: &Path, root: &Path, files: &mut Vec<SharedFile>) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            walk_dir(&path, root, files)?;
+        } else if meta.is_file() {
+            let name = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push(SharedFile {
+                path,
+                name,
+                size: meta.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Best-effort MIME type from the file extension.
+fn guess_mime(name: &str) -> &'static str {
+    let ext = name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "txt" | "log" => "text/plain",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "csv" => "text/csv",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" | "tgz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "mp4" => "video/mp4",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_files_walks_directories() {
+        let root = std::env::temp_dir().join("notir_test_share");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("dist/sub")).unwrap();
+        std::fs::write(root.join("dist/a.txt"), "aa").unwrap();
+        std::fs::write(root.join("dist/sub/b.bin"), "bbb").unwrap();
+        std::fs::write(root.join("single.json"), "{}").unwrap();
+
+        let files = collect_files(&[root.join("dist"), root.join("single.json")]).unwrap();
+        let names: Vec<(&str, u64)> = files.iter().map(|f| (f.name.as_str(), f.size)).collect();
+        assert_eq!(
+            names,
+            vec![("dist/a.txt", 2), ("dist/sub/b.bin", 3), ("single.json", 2)]
+        );
+        assert!(collect_files(&[root.join("missing")]).is_err());
+    }
+
+    #[test]
+    fn guess_mime_by_extension() {
+        assert_eq!(guess_mime("report.PDF"), "application/pdf");
+        assert_eq!(guess_mime("build.tar"), "application/x-tar");
+        assert_eq!(guess_mime("noext"), "application/octet-stream");
+    }
+}
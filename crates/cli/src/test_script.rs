@@ -0,0 +1,222 @@
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
+use crate::args::TestScriptArgs;
+use crate::client::ServerClient;
+use crate::js_runtime::{JsEngine, ScriptConfig};
+use crate::routes::Routed;
+use crate::script_api::WsEvent;
+
+/// Runs the transform over every input event, in order and in one engine so
+/// `state` carries across events. Results are printed one JSON line per event,
+/// or compared with `--expected`; a mismatch or JS error fails the run.
+pub async fn run(server: &ServerClient, args: &TestScriptArgs) -> Result<ExitCode> {
+    let config = ScriptConfig {
+        timeout: Duration::from_millis(args.script_timeout_ms),
+        fetch_allow: args.fetch_allow.clone(),
+        proxy: server.http_proxy(),
+        ..ScriptConfig::default()
+    };
+    let engine = JsEngine::new(Some(&args.script), config)?;
+    let input = read_lines(&args.input).await?;
+    let expected = match &args.expected {
+        Some(path) => Some(read_lines(path).await?),
+        None => None,
+    };
+
+    let mut failures = 0;
+    for (n, (line_no, line)) in input.iter().enumerate() {
+        let event = WsEvent::from_json_line(line, args.binary_encoding)
+            .with_context(|| format!("{}:{}: invalid event", args.input.display(), line_no))?;
+        let event_json = serde_json::to_string(&event)?;
+        let (actual, errored) = match engine.transform(&event_json, event.raw.as_deref()).await {
+            Ok(routed) => (result_json(&routed), false),
+            Err(e) => (json!({ "error": format!("{:#}", e) }), true),
+        };
+
+        let Some(expected) = &expected else {
+            println!("{}", actual);
+            failures += errored as usize;
+            continue;
+        };
+        let want = expected.get(n).map(|(_, want)| want.as_str());
+        let matches = match want {
+            Some(want) => {
+                let want: Value = serde_json::from_str(want)
+                    .with_context(|| format!("Invalid expected result for event {}", n + 1))?;
+                !errored && actual == want
+            }
+            None => false,
+        };
+        if !matches {
+            failures += 1;
+            eprintln!("event {} (line {}): mismatch", n + 1, line_no);
+            eprintln!("  expected: {}", want.unwrap_or("<missing>"));
+            eprintln!("  actual:   {}", actual);
+        }
+    }
+
+    if let Some(expected) = &expected {
+        if expected.len() > input.len() {
+            eprintln!(
+                "{} expected result(s) without an input event",
+                expected.len() - input.len()
+            );
+        }
+        eprintln!(
+            "{} event(s), {} passed, {} failed",
+            input.len(),
+            input.len() - failures,
+            failures
+        );
+        if expected.len() > input.len() {
+            return Ok(ExitCode::FAILURE);
+        }
+    } else if failures > 0 {
+        eprintln!("{} of {} event(s) failed", failures, input.len());
+    }
+    Ok(if failures == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+/// How a transform result is printed and compared: `null` when discarded,
+/// the string for a single default-output item, otherwise an array of
+/// `{target, data}` objects (a null `data` stands for the original message).
+fn result_json(routed: &[Routed]) -> Value {
+    match routed {
+        [] => Value::Null,
+        [
+            Routed {
+                target: None,
+                data: Some(data),
+            },
+        ] => Value::String(data.clone()),
+        _ => json!(routed),
+    }
+}
+
+/// Non-empty lines of a file (or stdin for `-`) with their line numbers.
+async fn read_lines(path: &Path) -> Result<Vec<(usize, String)>> {
+    let text = if path == Path::new("-") {
+        let mut text = String::new();
+        tokio::io::stdin()
+            .read_to_string(&mut text)
+            .await
+            .context("Failed to read stdin")?;
+        text
+    } else {
+        tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?
+    };
+    Ok(text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| (i + 1, line.to_string()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{Cli, Command};
+    use clap::Parser;
+
+    const SCRIPT: &str = r#"
+function transform(event) {
+    if (event.text === "boom") throw new Error("boom");
+    return event.text.toUpperCase();
+}
+"#;
+
+    /// Runs the command on fixtures written to a temp directory.
+    async fn run_case(name: &str, input: &str, expected: Option<&str>) -> ExitCode {
+        let dir = std::env::temp_dir().join(format!("notir_test_script_{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+        std::fs::write(dir.join("t.js"), SCRIPT).unwrap();
+        std::fs::write(dir.join("in.jsonl"), input).unwrap();
+        let mut argv = vec![
+            "notir-cli".to_string(),
+            "test-script".to_string(),
+            "--script".to_string(),
+            path("t.js"),
+            "--input".to_string(),
+            path("in.jsonl"),
+        ];
+        if let Some(expected) = expected {
+            std::fs::write(dir.join("expected.jsonl"), expected).unwrap();
+            argv.extend(["--expected".to_string(), path("expected.jsonl")]);
+        }
+        let cli = Cli::parse_from(argv);
+        let server = ServerClient::new(&cli).unwrap();
+        let Command::TestScript(args) = &cli.command else {
+            panic!("expected test-script");
+        };
+        run(&server, args).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn expected_results_decide_the_exit_code() {
+        let input = "{\"text\":\"a\"}\n\n{\"text\":\"b\"}\n";
+        assert_eq!(
+            run_case("match", input, Some("\"A\"\n\"B\"\n")).await,
+            ExitCode::SUCCESS
+        );
+        assert_eq!(
+            run_case("mismatch", input, Some("\"A\"\n\"b\"\n")).await,
+            ExitCode::FAILURE
+        );
+        assert_eq!(
+            run_case("too_few", input, Some("\"A\"\n")).await,
+            ExitCode::FAILURE
+        );
+        assert_eq!(
+            run_case("too_many", input, Some("\"A\"\n\"B\"\n\"C\"\n")).await,
+            ExitCode::FAILURE
+        );
+    }
+
+    #[tokio::test]
+    async fn script_exceptions_fail_the_run() {
+        let input = "{\"text\":\"a\"}\n{\"text\":\"boom\"}\n";
+        assert_eq!(run_case("throws", input, None).await, ExitCode::FAILURE);
+        // Even an expected error object does not make a throwing script pass.
+        let expected = "\"A\"\n{\"error\":\"boom\"}\n";
+        assert_eq!(
+            run_case("throws_expected", input, Some(expected)).await,
+            ExitCode::FAILURE
+        );
+        assert_eq!(
+            run_case("no_throw", "{\"text\":\"a\"}\n", None).await,
+            ExitCode::SUCCESS
+        );
+    }
+
+    #[test]
+    fn results_are_compact_for_plain_outputs() {
+        let plain = |data: &str| Routed {
+            target: None,
+            data: Some(data.to_string()),
+        };
+        assert_eq!(result_json(&[]), Value::Null);
+        assert_eq!(result_json(&[plain("hi")]), json!("hi"));
+        let routed = Routed {
+            target: Some("file:alerts".to_string()),
+            data: None,
+        };
+        assert_eq!(
+            result_json(&[plain("hi"), routed]),
+            json!([{ "target": null, "data": "hi" }, { "target": "file:alerts", "data": null }])
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use anyhow::{Context, Result};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+/// Longest wait between two delivery attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How often a non-empty spool is retried while no new messages arrive.
+const SPOOL_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    /// Attempts after the first one before a message is spooled.
+    pub retries: u32,
+    /// Delay before the first retry; doubled after every failure.
+    pub backoff: Duration,
+    /// Messages that could not be delivered, one JSON string per line.
+    pub spool: PathBuf,
+    /// `--proxy`; without it the proxy environment variables apply.
+    pub proxy: Option<reqwest::Proxy>,
+}
+
+/// POSTs every message to a URL from a background task, so a slow or
+/// unreachable target never holds up the WebSocket reader.
+pub struct Webhook {
+    tx: mpsc::UnboundedSender<String>,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+enum Delivery {
+    Delivered,
+    /// Worth retrying later: network error, 5xx or 429.
+    Retry(String),
+    /// The target refused the message; retrying will not help.
+    Rejected(String),
+}
+
+impl Webhook {
+    pub fn new(config: WebhookConfig) -> Result<Self> {
+        if let Some(dir) = config.spool.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in &config.headers {
+            headers.insert(
+                reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                    .with_context(|| format!("Invalid header name '{}'", name))?,
+                reqwest::header::HeaderValue::from_str(value)
+                    .with_context(|| format!("Invalid value for header '{}'", name))?,
+            );
+        }
+        if !headers.contains_key(reqwest::header::CONTENT_TYPE) {
+            headers.insert(
+                reqwest::header::CONTENT_TYPE,
+                reqwest::header::HeaderValue::from_static("text/plain; charset=utf-8"),
+            );
+        }
+        let mut client = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(30));
+        if let Some(proxy) = config.proxy.clone() {
+            client = client.proxy(proxy);
+        }
+        let client = client.build().context("Failed to build webhook client")?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (stop, stop_rx) = oneshot::channel();
+        let stop_rx = Stop {
+            rx: stop_rx,
+            stopped: false,
+        };
+        let task = tokio::spawn(deliver_loop(client, config, rx, stop_rx));
+        Ok(Self { tx, stop, task })
+    }
+
+    pub fn send(&self, msg: &str) {
+        if self.tx.send(msg.to_string()).is_err() {
+            tracing::error!("Webhook worker stopped, message lost");
+        }
+    }
+
+    /// Delivers (or spools) everything still queued. When `interrupted`,
+    /// the delivery in flight is abandoned and the queue is spooled without
+    /// contacting the target, so shutdown does not wait on retries.
+    pub async fn finish(self, interrupted: bool) {
+        let Self { tx, stop, task } = self;
+        drop(tx);
+        if interrupted {
+            let _ = stop.send(());
+        }
+        let _ = task.await;
+    }
+}
+
+/// Tells the worker to give up on the network and spool what is left.
+struct Stop {
+    rx: oneshot::Receiver<()>,
+    stopped: bool,
+}
+
+impl Stop {
+    async fn wait(&mut self) {
+        // The sender outlives the worker unless it is used.
+        if (&mut self.rx).await.is_ok() {
+            self.stopped = true;
+        } else {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Runs `work` unless a stop arrives first; `None` once stopped.
+    async fn guard<F: Future>(&mut self, work: F) -> Option<F::Output> {
+        if self.stopped {
+            return None;
+        }
+        tokio::select! {
+            biased;
+            _ = self.wait() => None,
+            out = work => Some(out),
+        }
+    }
+}
+
+async fn deliver_loop(
+    client: reqwest::Client,
+    config: WebhookConfig,
+    mut rx: mpsc::UnboundedReceiver<String>,
+    mut stop: Stop,
+) {
+    // Counted here so new messages are appended without rereading the spool.
+    let mut spooled = spool_len(&config.spool);
+    if spooled > 0 {
+        tracing::info!("{} spooled webhook message(s) pending", spooled);
+        spooled = replay_spool(&client, &config, &mut stop).await;
+    }
+    let mut retry = tokio::time::interval(SPOOL_RETRY_INTERVAL);
+    retry.tick().await;
+
+    while !stop.stopped {
+        tokio::select! {
+            biased;
+            _ = stop.wait() => {}
+            msg = rx.recv() => {
+                let Some(msg) = msg else { break };
+                if spooled > 0 {
+                    // Keep order: queue behind the spooled messages until the next retry.
+                    spool_messages(&config.spool, &[msg]);
+                    spooled += 1;
+                } else if !deliver_with_retries(&client, &config, &msg, &mut stop).await {
+                    spool_messages(&config.spool, &[msg]);
+                    spooled = 1;
+                    if !stop.stopped {
+                        tracing::warn!(
+                            "Webhook unreachable, spooled message to {}",
+                            config.spool.display()
+                        );
+                    }
+                }
+            }
+            _ = retry.tick(), if spooled > 0 => {
+                spooled = replay_spool(&client, &config, &mut stop).await;
+            }
+        }
+    }
+
+    let mut queued = Vec::new();
+    while let Ok(msg) = rx.try_recv() {
+        queued.push(msg);
+    }
+    if !queued.is_empty() {
+        spool_messages(&config.spool, &queued);
+        tracing::info!(
+            "Spooled {} queued webhook message(s) to {}",
+            queued.len(),
+            config.spool.display()
+        );
+    }
+}
+
+async fn post(client: &reqwest::Client, url: &str, msg: &str) -> Delivery {
+    match client.post(url).body(msg.to_string()).send().await {
+        Ok(resp) if resp.status().is_success() => Delivery::Delivered,
+        Ok(resp) => {
+            let status = resp.status();
+            if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                Delivery::Retry(status.to_string())
+            } else {
+                Delivery::Rejected(status.to_string())
+            }
+        }
+        Err(e) => Delivery::Retry(e.to_string()),
+    }
+}
+
+/// Returns false when the message should be spooled, including on a stop.
+async fn deliver_with_retries(
+    client: &reqwest::Client,
+    config: &WebhookConfig,
+    msg: &str,
+    stop: &mut Stop,
+) -> bool {
+    for attempt in 0..=config.retries {
+        if attempt > 0 {
+            let delay = tokio::time::sleep(backoff_delay(config.backoff, attempt));
+            if stop.guard(delay).await.is_none() {
+                return false;
+            }
+        }
+        let Some(delivery) = stop.guard(post(client, &config.url, msg)).await else {
+            return false;
+        };
+        match delivery {
+            Delivery::Delivered => return true,
+            Delivery::Rejected(reason) => {
+                tracing::error!("Webhook rejected message ({}), dropping it", reason);
+                return true;
+            }
+            Delivery::Retry(reason) => {
+                tracing::warn!(
+                    "Webhook delivery failed ({}), attempt {}/{}",
+                    reason,
+                    attempt + 1,
+                    config.retries + 1
+                );
+            }
+        }
+    }
+    false
+}
+
+/// Delay before retry number `attempt` (1-based).
+fn backoff_delay(base: Duration, attempt: u32) -> Duration {
+    base.saturating_mul(1 << (attempt - 1).min(16))
+        .min(MAX_BACKOFF)
+}
+
+/// Sends spooled messages in order, one attempt each, stopping at the first
+/// failure or a stop. Returns how many messages are left in the spool.
+async fn replay_spool(client: &reqwest::Client, config: &WebhookConfig, stop: &mut Stop) -> usize {
+    let pending = match spool_read(&config.spool) {
+        Ok(pending) => pending,
+        Err(e) => {
+            tracing::error!("Failed to read webhook spool: {:#}", e);
+            return 0;
+        }
+    };
+    let mut sent = 0;
+    for msg in &pending {
+        let Some(delivery) = stop.guard(post(client, &config.url, msg)).await else {
+            break;
+        };
+        match delivery {
+            Delivery::Delivered => sent += 1,
+            Delivery::Rejected(reason) => {
+                tracing::error!("Webhook rejected spooled message ({}), dropping it", reason);
+                sent += 1;
+            }
+            Delivery::Retry(_) => break,
+        }
+    }
+    if sent > 0 {
+        tracing::info!("Delivered {} spooled webhook message(s)", sent);
+    }
+    let left = &pending[sent..];
+    if let Err(e) = spool_write(&config.spool, left) {
+        tracing::error!("Failed to rewrite webhook spool: {:#}", e);
+    }
+    left.len()
+}
+
+fn spool_messages(path: &Path, messages: &[String]) {
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|file| {
+            let mut file = std::io::BufWriter::new(file);
+            for msg in messages {
+                writeln!(file, "{}", serde_json::Value::from(msg.as_str()))?;
+            }
+            file.flush()
+        });
+    match result {
+        Ok(()) => tracing::debug!(
+            "Spooled {} webhook message(s) to {}",
+            messages.len(),
+            path.display()
+        ),
+        Err(e) => tracing::error!("Failed to spool webhook message: {}", e),
+    }
+}
+
+fn spool_read(path: &Path) -> Result<Vec<String>> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut messages = Vec::new();
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<String>(&line) {
+            Ok(msg) => messages.push(msg),
+            Err(_) => tracing::warn!("Skipping corrupt spool line in {}", path.display()),
+        }
+    }
+    Ok(messages)
+}
+
+fn spool_write(path: &Path, messages: &[String]) -> Result<()> {
+    if messages.is_empty() {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => return Ok(()),
+        }
+    }
+    let tmp = path.with_extension("tmp");
+    {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+        for msg in messages {
+            writeln!(file, "{}", serde_json::Value::from(msg.as_str()))?;
+        }
+        file.flush()?;
+    }
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn spool_len(path: &Path) -> usize {
+    spool_read(path).map(|m| m.len()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_cap() {
+        let base = Duration::from_millis(500);
+        assert_eq!(backoff_delay(base, 1), Duration::from_millis(500));
+        assert_eq!(backoff_delay(base, 2), Duration::from_secs(1));
+        assert_eq!(backoff_delay(base, 4), Duration::from_secs(4));
+        assert_eq!(backoff_delay(base, 30), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn spool_round_trips_multiline_messages() {
+        let path = std::env::temp_dir().join("notir_test_webhook.spool");
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(spool_len(&path), 0);
+
+        spool_messages(&path, &["line one\nline two".to_string()]);
+        spool_messages(&path, &["{\"a\":1}".to_string()]);
+        assert_eq!(
+            spool_read(&path).unwrap(),
+            vec!["line one\nline two".to_string(), "{\"a\":1}".to_string()]
+        );
+
+        spool_write(&path, &["{\"a\":1}".to_string()]).unwrap();
+        assert_eq!(spool_len(&path), 1);
+        spool_write(&path, &[]).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn interrupted_finish_spools_the_queue_without_waiting() {
+        // A target that accepts connections and never answers.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((conn, _)) = listener.accept().await {
+                held.push(conn);
+            }
+        });
+        let spool = std::env::temp_dir().join("notir_test_webhook_stop.spool");
+        let _ = std::fs::remove_file(&spool);
+        let webhook = Webhook::new(WebhookConfig {
+            url,
+            headers: Vec::new(),
+            retries: 5,
+            backoff: Duration::from_secs(1),
+            spool: spool.clone(),
+            proxy: None,
+        })
+        .unwrap();
+        for msg in ["a", "b", "c"] {
+            webhook.send(msg);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        tokio::time::timeout(Duration::from_secs(2), webhook.finish(true))
+            .await
+            .expect("finish waited on the network");
+        assert_eq!(spool_read(&spool).unwrap(), ["a", "b", "c"]);
+    }
+}
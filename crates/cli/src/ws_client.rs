@@ -1,56 +1,106 @@
 use anyhow::{Context, Result};
-use futures_util::StreamExt;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::protocol::Message;
 
-use crate::args::SubscriptionMode;
+use crate::args::{BinaryEncoding, SubscriptionMode};
+use crate::backoff::Backoff;
+use crate::client::ServerClient;
+use crate::exec::{ExecEnv, ExecHook};
 use crate::js_runtime::JsEngine;
 use crate::output::OutputWriter;
+use crate::replay::Recorder;
+use crate::routes::{Routed, Target};
 use crate::script_api::WsEvent;
 
+/// How often `--watch-script` checks the script files for changes.
+const SCRIPT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 pub async fn run_client(
-    server: &str,
-    id: &str,
-    mode: SubscriptionMode,
-    js_engine: &JsEngine,
+    server: &ServerClient,
+    handler: &EventHandler<'_>,
     writer: &mut OutputWriter,
+    mut recorder: Option<&mut Recorder>,
+    backoff: &mut Backoff,
+    ping_timeout: Option<Duration>,
 ) -> Result<()> {
-    let url = format!("{}/{}/sub?id={}", server.trim_end_matches('/'), mode, id);
+    let mode = handler.mode;
+    let mut query = vec![("id", handler.id)];
+    if mode == SubscriptionMode::Single {
+        // Tagged frames tell shot messages from ping_pong requests.
+        query.push(("tagged", "true"));
+    }
+    let url = server.ws_url(&[&mode.to_string(), "sub"], &query);
 
     tracing::info!("Connecting to {}", url);
 
-    let (mut ws_stream, _) = connect_async(&url)
+    let mut ws_stream = server
+        .connect(&url)
         .await
         .with_context(|| format!("Failed to connect to {}", url))?;
 
     tracing::info!("Connected to {}", url);
+    backoff.connected();
 
     let source = mode.to_string();
+    let watch = handler.js_engine.watches_script();
+    let mut script_poll = tokio::time::interval(SCRIPT_POLL_INTERVAL);
 
-    while let Some(msg) = ws_stream.next().await {
-        match msg {
-            Ok(Message::Text(text)) => {
-                let event = WsEvent::from_text(text.to_string(), &source);
-                handle_event(&event, js_engine, writer)?;
+    // Any frame proves the connection alive; the server pings idle ones.
+    let mut dead_at = Instant::now() + ping_timeout.unwrap_or_default();
+
+    loop {
+        let msg = tokio::select! {
+            msg = ws_stream.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = script_poll.tick(), if watch => {
+                handler.js_engine.reload_if_changed();
+                continue;
             }
-            Ok(Message::Binary(data)) => {
-                let event = WsEvent::from_binary(&data, &source);
-                handle_event(&event, js_engine, writer)?;
+            _ = tokio::time::sleep_until(dead_at), if ping_timeout.is_some() => {
+                anyhow::bail!(
+                    "Nothing received from the server for {}s, connection presumed dead",
+                    ping_timeout.unwrap_or_default().as_secs()
+                );
             }
+        };
+        dead_at = Instant::now() + ping_timeout.unwrap_or_default();
+        let (event, request_id) = match msg {
+            Ok(Message::Text(text)) => (
+                WsEvent::from_text(text.to_string(), &source, handler.id),
+                None,
+            ),
+            Ok(Message::Binary(data)) => handler.binary_event(&data, &source),
             Ok(Message::Ping(_)) => {
                 tracing::debug!("Received ping, auto-responding with pong");
+                continue;
             }
             Ok(Message::Pong(_)) => {
                 tracing::debug!("Received pong");
+                continue;
             }
             Ok(Message::Close(frame)) => {
                 tracing::info!("Server closed connection: {:?}", frame);
                 break;
             }
-            Ok(_) => {}
+            Ok(_) => continue,
             Err(e) => {
                 tracing::warn!("WebSocket error: {}", e);
                 break;
             }
+        };
+        if let Some(recorder) = recorder.as_deref_mut() {
+            recorder.record(&event)?;
+        }
+        let reply = handler.handle(&event, request_id.is_some(), writer).await?;
+        if let (Some(request_id), Some(reply)) = (request_id, reply) {
+            ws_stream
+                .send(Message::binary(reply_frame(&request_id, &reply)))
+                .await?;
         }
     }
 
@@ -58,21 +108,204 @@ pub async fn run_client(
     Ok(())
 }
 
-fn handle_event(event: &WsEvent, js_engine: &JsEngine, writer: &mut OutputWriter) -> Result<()> {
-    let event_json = serde_json::to_string(event)?;
-    match js_engine.transform(&event_json) {
-        Ok(Some(output)) => {
-            writer.write_message(&output)?;
+/// Turns received messages into output, command runs and replies.
+pub struct EventHandler<'a> {
+    id: &'a str,
+    mode: SubscriptionMode,
+    binary_encoding: BinaryEncoding,
+    js_engine: &'a JsEngine,
+    exec: Option<&'a ExecHook>,
+    exec_reply: bool,
+}
+
+impl<'a> EventHandler<'a> {
+    pub fn new(
+        id: &'a str,
+        mode: SubscriptionMode,
+        binary_encoding: BinaryEncoding,
+        js_engine: &'a JsEngine,
+        exec: Option<&'a ExecHook>,
+        exec_reply: bool,
+    ) -> Self {
+        Self {
+            id,
+            mode,
+            binary_encoding,
+            js_engine,
+            exec,
+            exec_reply,
         }
-        Ok(None) => {
-            tracing::debug!("Message discarded by transform script");
+    }
+
+    /// Whether a ping_pong request is answered. Only single mode routes frames
+    /// from the subscriber back to a ping_pong publisher. Checked per message,
+    /// as a reloaded script may add or drop `reply`.
+    fn replies(&self, ping_pong: bool) -> bool {
+        ping_pong
+            && self.mode == SubscriptionMode::Single
+            && (self.exec_reply || self.js_engine.has_reply())
+    }
+
+    /// Builds the event for a binary frame, unwrapping a tagged single-mode
+    /// frame. Returns the ping_pong request id when the frame carries one.
+    fn binary_event(&self, data: &[u8], source: &str) -> (WsEvent, Option<String>) {
+        let tagged = match self.mode {
+            SubscriptionMode::Single => untag_frame(data),
+            _ => None,
+        };
+        let Some((header, payload)) = tagged else {
+            let event = WsEvent::from_binary(data, source, self.id, self.binary_encoding);
+            return (event, None);
+        };
+        let event = if header.msg_type.as_deref() == Some("text") {
+            let text = String::from_utf8_lossy(payload).into_owned();
+            WsEvent::from_text(text, source, self.id)
+        } else {
+            WsEvent::from_binary(payload, source, self.id, self.binary_encoding)
+        };
+        (event, header.request_id)
+    }
+
+    /// Transforms and outputs the event; returns the reply to send back, if any.
+    /// Only ping_pong requests get one.
+    pub async fn handle(
+        &self,
+        event: &WsEvent,
+        ping_pong: bool,
+        writer: &mut OutputWriter,
+    ) -> Result<Option<String>> {
+        let reply = self.process(event, self.replies(ping_pong), writer).await;
+        self.js_engine.maybe_save_state();
+        reply
+    }
+
+    async fn process(
+        &self,
+        event: &WsEvent,
+        replies: bool,
+        writer: &mut OutputWriter,
+    ) -> Result<Option<String>> {
+        let event_json = serde_json::to_string(event)?;
+        let outputs = match self
+            .js_engine
+            .transform(&event_json, event.raw.as_deref())
+            .await
+        {
+            Ok(routed) if routed.is_empty() => {
+                tracing::debug!("Message discarded by transform script");
+                return self.script_reply(replies, event, &event_json).await;
+            }
+            Ok(routed) => self.route(event, routed, writer)?,
+            Err(e) => {
+                tracing::warn!("{:#}, outputting raw message", e);
+                let output = transform_error_output(event);
+                writer.write_event(event, &output, Some(&format!("{:#}", e)))?;
+                vec![output]
+            }
+        };
+
+        let Some(exec) = self.exec else {
+            return self.script_reply(replies, event, &event_json).await;
+        };
+        let env = ExecEnv::new(self.id, event);
+        let Some((first, rest)) = outputs.split_first() else {
+            return self.script_reply(replies, event, &event_json).await;
+        };
+        for output in rest {
+            exec.submit(output, env.clone());
+        }
+        if !(replies && self.exec_reply) {
+            exec.submit(first, env);
+            return self.script_reply(replies, event, &event_json).await;
         }
-        Err(e) => {
-            tracing::warn!("JS transform error: {}, outputting raw message", e);
-            writer.write_message(&transform_error_output(event))?;
+        match exec.run_for_reply(first, &env).await {
+            Ok(reply) => Ok(Some(reply)),
+            Err(e) => {
+                // No reply: the publisher gets a 408 instead of a made-up answer.
+                tracing::warn!("Exec hook failed: {:#}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Sends routed items to their targets and writes the rest to the main
+    /// output. Returns the main-output items, which also go to `--exec`.
+    fn route(
+        &self,
+        event: &WsEvent,
+        routed: Vec<Routed>,
+        writer: &mut OutputWriter,
+    ) -> Result<Vec<String>> {
+        let mut outputs = Vec::new();
+        for Routed { target, data } in routed {
+            let data = data.unwrap_or_else(|| event.raw_output().to_string());
+            if let Some(target) = target.as_deref() {
+                match Target::parse(target) {
+                    Ok(Target::Default) => {}
+                    Ok(parsed) => {
+                        if writer.write_routed(parsed, event, &data)? {
+                            continue;
+                        }
+                        tracing::warn!(
+                            "No route declared for '{}', using the default output",
+                            target
+                        );
+                    }
+                    Err(e) => tracing::warn!("{}, using the default output", e),
+                }
+            }
+            writer.write_event(event, &data, None)?;
+            outputs.push(data);
+        }
+        Ok(outputs)
+    }
+
+    async fn script_reply(
+        &self,
+        replies: bool,
+        event: &WsEvent,
+        event_json: &str,
+    ) -> Result<Option<String>> {
+        if !replies {
+            return Ok(None);
+        }
+        match self.js_engine.reply(event_json, event.raw.as_deref()).await {
+            Ok(reply) => Ok(reply),
+            Err(e) => {
+                // No reply: the publisher gets a 408 instead of a made-up answer.
+                tracing::warn!("{:#}", e);
+                Ok(None)
+            }
         }
     }
-    Ok(())
+}
+
+/// Header line of a frame on a tagged single-mode connection.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FrameHeader {
+    /// `text` or `binary`: how the payload was published.
+    #[serde(rename = "type")]
+    msg_type: Option<String>,
+    /// Set on ping_pong requests; the reply must carry it back.
+    request_id: Option<String>,
+}
+
+/// Splits a tagged frame into its header and payload.
+fn untag_frame(data: &[u8]) -> Option<(FrameHeader, &[u8])> {
+    let newline = data.iter().position(|&b| b == b'\n')?;
+    let header = serde_json::from_slice(&data[..newline]).ok()?;
+    Some((header, &data[newline + 1..]))
+}
+
+/// Wraps a reply so the server hands it to the request it answers.
+fn reply_frame(request_id: &str, reply: &str) -> Vec<u8> {
+    let mut frame = serde_json::json!({ "requestId": request_id })
+        .to_string()
+        .into_bytes();
+    frame.push(b'\n');
+    frame.extend_from_slice(reply.as_bytes());
+    frame
 }
 
 fn transform_error_output(event: &WsEvent) -> String {
@@ -81,12 +314,84 @@ fn transform_error_output(event: &WsEvent) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::transform_error_output;
-    use crate::script_api::WsEvent;
+    use super::*;
+    use crate::args::{FileMode, OutputFormat, OutputMode};
+    use crate::js_runtime::ScriptConfig;
+    use crate::output::OutputConfig;
+    use crate::rotation::RotationConfig;
+    use crate::routes::Routes;
+
+    #[tokio::test]
+    async fn single_mode_replies_to_ping_pong_requests_only() {
+        let script = std::env::temp_dir().join("notir_test_mixed_reply.js");
+        std::fs::write(
+            &script,
+            r#"
+function transform(event) { return null; }
+function reply(event) { return "answer:" + event.text; }
+"#,
+        )
+        .unwrap();
+        let engine =
+            JsEngine::new(Some(script.to_str().unwrap()), ScriptConfig::default()).unwrap();
+        let output = OutputConfig {
+            format: OutputFormat::Text,
+            mode: OutputMode::Stdout,
+            file_mode: FileMode::Append,
+            output_dir: std::env::temp_dir().to_str().unwrap().to_string(),
+            raw_binary: false,
+        };
+        let mut writer = OutputWriter::new(
+            output,
+            "u1",
+            RotationConfig::default(),
+            None,
+            Routes::default(),
+        )
+        .unwrap();
+        let handler = EventHandler::new(
+            "u1",
+            SubscriptionMode::Single,
+            BinaryEncoding::Hex,
+            &engine,
+            None,
+            false,
+        );
+
+        let (shot, request_id) =
+            handler.binary_event(b"{\"mode\":\"shot\",\"type\":\"text\"}\nstatus", "single");
+        assert_eq!(shot.text.as_deref(), Some("status"));
+        assert_eq!(request_id, None);
+        let reply = handler.handle(&shot, request_id.is_some(), &mut writer);
+        assert_eq!(reply.await.unwrap(), None);
+
+        let frame = b"{\"mode\":\"ping_pong\",\"type\":\"text\",\"requestId\":\"r1\"}\n42";
+        let (ping_pong, request_id) = handler.binary_event(frame, "single");
+        assert_eq!(request_id.as_deref(), Some("r1"));
+        let reply = handler.handle(&ping_pong, true, &mut writer).await.unwrap();
+        assert_eq!(reply.as_deref(), Some("answer:42"));
+        assert_eq!(
+            reply_frame("r1", "answer:42"),
+            b"{\"requestId\":\"r1\"}\nanswer:42"
+        );
+
+        let broad = EventHandler::new(
+            "u1",
+            SubscriptionMode::Broad,
+            BinaryEncoding::Hex,
+            &engine,
+            None,
+            false,
+        );
+        let (event, request_id) = broad.binary_event(frame, "broad");
+        assert_eq!(request_id, None);
+        assert_eq!(event.text, None);
+        assert!(!broad.replies(true));
+    }
 
     #[test]
     fn transform_error_output_keeps_binary_payload() {
-        let event = WsEvent::from_binary(&[0xca, 0xfe], "single");
+        let event = WsEvent::from_binary(&[0xca, 0xfe], "single", "u1", BinaryEncoding::Hex);
         assert_eq!(transform_error_output(&event), "[TRANSFORM_ERROR] cafe");
     }
 }
@@ -1,5 +1,5 @@
-use std::sync::LazyLock;
-use std::time::Duration;
+use std::sync::{LazyLock, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use salvo::http::body::{BodySender, ResBody};
 use salvo::prelude::*;
@@ -11,9 +11,11 @@ use nanoid::nanoid;
 use serde::Deserialize;
 use serde_json::json;
 use tokio::sync::mpsc;
-use tokio::time::timeout;
+use tokio::time::{Instant, timeout};
 
 use crate::broadcast::BROADCAST_USERS;
+use crate::links;
+use crate::zip::{self, ZipStream};
 
 /// 单个连接同时只允许一个在途传输，分块通道容量即背压窗口
 const TRANSFER_CHANNEL_CAPACITY: usize = 16;
@@ -22,6 +24,15 @@ const CHUNK_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 const MAX_OFFERS_PER_CONNECTION: usize = 32;
 /// 文件名截断上限（字节），避免畸形头部长度
 const MAX_FILE_NAME_BYTES: usize = 255;
+/// 打包下载时等待持有方传输槽空出的轮询间隔
+const SLOT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+/// 签名下载链接的默认与最长有效期
+const DEFAULT_LINK_TTL_SECS: u64 = 3600;
+const MAX_LINK_TTL_SECS: u64 = 7 * 24 * 3600;
+/// 下载口令可通过该请求头或 `password` 查询参数提供
+const PASSWORD_HEADER: &str = "x-notir-password";
+/// 过期 offer 清理任务的扫描间隔
+const OFFER_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
 
 /// 客户端通过广播 WS 发来的文件操作（text JSON 帧）
 #[derive(Deserialize, Debug)]
@@ -34,9 +45,65 @@ enum ClientOp {
         size: u64,
         #[serde(default)]
         mime: Option<String>,
+        /// 存活秒数，缺省或为 0 表示随连接存活
+        #[serde(default)]
+        ttl: Option<u64>,
+        #[serde(flatten)]
+        access: OfferAccess,
+    },
+    OfferBundle {
+        #[serde(default, rename = "offerId")]
+        offer_id: Option<String>,
+        name: String,
+        files: Vec<BundleFile>,
+        #[serde(default)]
+        ttl: Option<u64>,
+        #[serde(flatten)]
+        access: OfferAccess,
+    },
+    /// 持有方为自己的 offer 申请签名下载链接
+    SignLink {
+        #[serde(rename = "fileId")]
+        file_id: String,
+        #[serde(default, rename = "expiresIn")]
+        expires_in: Option<u64>,
+    },
+    Withdraw {
+        #[serde(rename = "fileId")]
+        file_id: String,
     },
     Done,
     Abort,
+    ListOffers,
+}
+
+/// offer 的下载访问控制，随 offer/offer_bundle 指令一起提交
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OfferAccess {
+    /// 允许的下载次数，用尽后 offer 被移除
+    #[serde(default)]
+    pub max_downloads: Option<u32>,
+    /// 下载口令
+    #[serde(default)]
+    pub password: Option<String>,
+    /// 只接受服务端签发的签名链接
+    #[serde(default)]
+    pub signed_only: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct BundleFile {
+    name: String,
+    size: u64,
+}
+
+/// 打包 offer 中的单个成员；持有方按成员 fileId 响应 pull
+#[derive(Debug, Clone)]
+pub struct BundleMember {
+    pub file_id: String,
+    pub name: String,
+    pub size: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -46,6 +113,25 @@ pub struct Offer {
     pub name: String,
     pub size: u64,
     pub mime: String,
+    /// 登记时间（Unix 毫秒）
+    pub created_at: u64,
+    /// 过期时间（Unix 毫秒），None 表示随连接存活
+    pub expires_at: Option<u64>,
+    /// 打包成员，普通文件为空
+    pub members: Vec<BundleMember>,
+    pub access: OfferAccess,
+    /// 已开始的下载次数
+    pub downloads: u32,
+}
+
+impl Offer {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    pub fn is_bundle(&self) -> bool {
+        !self.members.is_empty()
+    }
 }
 
 #[derive(Debug)]
@@ -55,9 +141,35 @@ pub(crate) enum TransferEvent {
     Aborted,
 }
 
+/// 文件传输的大小与带宽限制，`None` 表示不限
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileLimits {
+    /// 单个文件（打包时为单个成员）的最大字节数
+    pub max_file_size: Option<u64>,
+    /// 同一房间内全部 offer 的声明字节数之和
+    pub max_room_bytes: Option<u64>,
+    /// 同一连接全部 offer 的声明字节数之和
+    pub max_connection_bytes: Option<u64>,
+    /// 同一房间全部下载合计的字节/秒上限
+    pub room_bandwidth: Option<u64>,
+}
+
+static FILE_LIMITS: OnceLock<FileLimits> = OnceLock::new();
+
+/// 启动时设置一次限制；未设置时按不限处理
+pub fn init_limits(limits: FileLimits) {
+    let _ = FILE_LIMITS.set(limits);
+}
+
+fn limits() -> FileLimits {
+    FILE_LIMITS.get().copied().unwrap_or_default()
+}
+
 pub static FILE_OFFERS: LazyLock<DashMap<String, Offer>> = LazyLock::new(DashMap::default);
 pub(crate) static ACTIVE_TRANSFERS: LazyLock<DashMap<u64, mpsc::Sender<TransferEvent>>> =
     LazyLock::new(DashMap::default);
+/// 每个房间下载带宽的下一个空闲时刻，房间内全部下载共享
+static ROOM_BANDWIDTH: LazyLock<DashMap<String, Instant>> = LazyLock::new(DashMap::default);
 
 /// 原子占用传输槽；同连接已有在途传输时返回 None
 pub(crate) fn try_start_transfer(conn_id: u64) -> Option<mpsc::Receiver<TransferEvent>> {
@@ -88,59 +200,277 @@ pub async fn handle_client_op(room_id: &str, conn_id: u64, text: &str) {
             name,
             size,
             mime,
+            ttl,
+            access,
         } => {
-            let offered = FILE_OFFERS
-                .iter()
-                .filter(|entry| entry.value().conn_id == conn_id)
-                .count();
-            if offered >= MAX_OFFERS_PER_CONNECTION {
-                tracing::warn!(
-                    "rejecting file offer from {room_id} (conn {conn_id}): too many offers"
-                );
-                send_control(
-                    room_id,
-                    conn_id,
-                    &json!({"op": "error", "message": "too many offers"}),
-                )
-                .await;
+            if offer_rejected(room_id, conn_id, &[size]).await {
                 return;
             }
 
-            let mut name = name.trim().to_string();
-            if name.is_empty() {
-                name = "unnamed".to_string();
+            let name = clean_file_name(&name);
+            let file_id = nanoid!();
+            let created_at = now_millis();
+            let offer = Offer {
+                room_id: room_id.to_string(),
+                conn_id,
+                name: name.clone(),
+                size,
+                mime: mime
+                    .filter(|m| !m.is_empty())
+                    .unwrap_or_else(|| "application/octet-stream".to_string()),
+                created_at,
+                expires_at: expires_at(created_at, ttl),
+                members: Vec::new(),
+                access: clean_access(access),
+                downloads: 0,
+            };
+            tracing::info!(
+                "file offer registered: room={room_id} conn={conn_id} file={file_id} name={name} size={size}"
+            );
+
+            let mut control = json!({"op": "offer_ok", "fileId": file_id});
+            if let Some(offer_id) = offer_id {
+                control["offerId"] = json!(offer_id);
             }
-            while name.len() > MAX_FILE_NAME_BYTES {
-                name.pop();
+            register_offer(file_id, offer, &control).await;
+        }
+        ClientOp::OfferBundle {
+            offer_id,
+            name,
+            files,
+            ttl,
+            access,
+        } => {
+            let total: u64 = files.iter().map(|f| f.size).fold(0, u64::saturating_add);
+            let rejection = if files.is_empty() {
+                Some("empty bundle")
+            } else if files.len() > zip::MAX_ZIP_ENTRIES {
+                Some("too many files in bundle")
+            } else if total > zip::MAX_ZIP_BYTES {
+                Some("bundle too large")
+            } else {
+                None
+            };
+            if let Some(message) = rejection {
+                tracing::warn!("rejecting bundle offer from {room_id} (conn {conn_id}): {message}");
+                send_control(room_id, conn_id, &json!({"op": "error", "message": message}))
+                    .await;
+                return;
+            }
+            let sizes: Vec<u64> = files.iter().map(|f| f.size).collect();
+            if offer_rejected(room_id, conn_id, &sizes).await {
+                return;
             }
 
+            let mut name = clean_file_name(&name);
+            if !name.to_ascii_lowercase().ends_with(".zip") {
+                name.push_str(".zip");
+            }
+            let members: Vec<BundleMember> = files
+                .into_iter()
+                .map(|file| BundleMember {
+                    file_id: nanoid!(),
+                    name: clean_member_path(&file.name),
+                    size: file.size,
+                })
+                .collect();
             let file_id = nanoid!();
-            FILE_OFFERS.insert(
-                file_id.clone(),
-                Offer {
-                    room_id: room_id.to_string(),
-                    conn_id,
-                    name: name.clone(),
-                    size,
-                    mime: mime
-                        .filter(|m| !m.is_empty())
-                        .unwrap_or_else(|| "application/octet-stream".to_string()),
-                },
-            );
+            let created_at = now_millis();
             tracing::info!(
-                "file offer registered: room={room_id} conn={conn_id} file={file_id} name={name} size={size}"
+                "bundle offer registered: room={room_id} conn={conn_id} file={file_id} name={name} files={} size={total}",
+                members.len()
             );
 
-            let mut control = json!({"op": "offer_ok", "fileId": file_id});
+            let mut control = json!({
+                "op": "bundle_ok",
+                "fileId": file_id,
+                "files": members
+                    .iter()
+                    .map(|m| json!({"fileId": m.file_id, "name": m.name}))
+                    .collect::<Vec<_>>(),
+            });
             if let Some(offer_id) = offer_id {
                 control["offerId"] = json!(offer_id);
             }
-            send_control(room_id, conn_id, &control).await;
+            let offer = Offer {
+                room_id: room_id.to_string(),
+                conn_id,
+                name,
+                size: total,
+                mime: "application/zip".to_string(),
+                created_at,
+                expires_at: expires_at(created_at, ttl),
+                members,
+                access: clean_access(access),
+                downloads: 0,
+            };
+            register_offer(file_id, offer, &control).await;
+        }
+        ClientOp::Withdraw { file_id } => {
+            let owned = FILE_OFFERS
+                .get(&file_id)
+                .is_some_and(|entry| entry.value().conn_id == conn_id);
+            if !owned {
+                send_control(
+                    room_id,
+                    conn_id,
+                    &json!({"op": "error", "message": "unknown file", "fileId": file_id}),
+                )
+                .await;
+                return;
+            }
+            remove_offer(&file_id, "withdrawn").await;
+            tracing::info!("file offer withdrawn: room={room_id} conn={conn_id} file={file_id}");
+        }
+        ClientOp::SignLink {
+            file_id,
+            expires_in,
+        } => {
+            let owned = FILE_OFFERS
+                .get(&file_id)
+                .is_some_and(|entry| entry.value().conn_id == conn_id);
+            if !owned {
+                send_control(
+                    room_id,
+                    conn_id,
+                    &json!({"op": "error", "message": "unknown file", "fileId": file_id}),
+                )
+                .await;
+                return;
+            }
+            let ttl = expires_in
+                .unwrap_or(DEFAULT_LINK_TTL_SECS)
+                .clamp(1, MAX_LINK_TTL_SECS);
+            let expires = now_millis() / 1000 + ttl;
+            let url = signed_download_path(&file_id, expires);
+            send_control(
+                room_id,
+                conn_id,
+                &json!({"op": "link", "fileId": file_id, "url": url, "expires": expires}),
+            )
+            .await;
         }
         ClientOp::Done => finish_transfer(conn_id, TransferEvent::Done).await,
         ClientOp::Abort => finish_transfer(conn_id, TransferEvent::Aborted).await,
+        ClientOp::ListOffers => {
+            let files = room_offers(room_id).await;
+            send_control(room_id, conn_id, &json!({"op": "offers", "files": files})).await;
+        }
+    }
+}
+
+/// 超出 offer 数或字节配额时回 error 控制消息并返回 true
+async fn offer_rejected(room_id: &str, conn_id: u64, sizes: &[u64]) -> bool {
+    let Err(message) = check_offer_limits(&limits(), room_id, conn_id, sizes) else {
+        return false;
+    };
+    tracing::warn!("rejecting file offer from {room_id} (conn {conn_id}): {message}");
+    send_control(room_id, conn_id, &json!({"op": "error", "message": message})).await;
+    true
+}
+
+/// 校验新 offer（打包时 `sizes` 为各成员大小）是否超出 offer 数、单文件与房间/连接配额
+pub(crate) fn check_offer_limits(
+    limits: &FileLimits,
+    room_id: &str,
+    conn_id: u64,
+    sizes: &[u64],
+) -> Result<(), &'static str> {
+    let mut offered = 0;
+    let mut conn_bytes = 0u64;
+    let mut room_bytes = 0u64;
+    for entry in FILE_OFFERS.iter() {
+        let offer = entry.value();
+        if offer.conn_id == conn_id {
+            offered += 1;
+            conn_bytes = conn_bytes.saturating_add(offer.size);
+        }
+        if offer.room_id == room_id {
+            room_bytes = room_bytes.saturating_add(offer.size);
+        }
+    }
+    if offered >= MAX_OFFERS_PER_CONNECTION {
+        return Err("too many offers");
+    }
+
+    if let Some(max) = limits.max_file_size
+        && sizes.iter().any(|size| *size > max)
+    {
+        return Err("file too large");
+    }
+    let total = sizes.iter().copied().fold(0, u64::saturating_add);
+    if let Some(max) = limits.max_connection_bytes
+        && conn_bytes.saturating_add(total) > max
+    {
+        return Err("connection quota exceeded");
+    }
+    if let Some(max) = limits.max_room_bytes
+        && room_bytes.saturating_add(total) > max
+    {
+        return Err("room quota exceeded");
+    }
+    Ok(())
+}
+
+/// 登记 offer，回复持有方并通知房间
+async fn register_offer(file_id: String, offer: Offer, control: &serde_json::Value) {
+    let room_id = offer.room_id.clone();
+    let conn_id = offer.conn_id;
+    let added = json!({"op": "offer_added", "file": offer_json(&file_id, &offer)});
+    FILE_OFFERS.insert(file_id, offer);
+    send_control(&room_id, conn_id, control).await;
+    broadcast_control(&room_id, &added).await;
+}
+
+fn clean_access(mut access: OfferAccess) -> OfferAccess {
+    access.password = access.password.filter(|p| !p.is_empty());
+    access
+}
+
+/// 签名下载链接的路径部分（不含协议与主机）
+pub(crate) fn signed_download_path(file_id: &str, expires: u64) -> String {
+    format!(
+        "/files/download/{}?expires={expires}&sig={}",
+        percent_encode(file_id),
+        links::sign(file_id, expires)
+    )
+}
+
+fn expires_at(created_at: u64, ttl: Option<u64>) -> Option<u64> {
+    ttl.filter(|ttl| *ttl > 0)
+        .map(|ttl| created_at.saturating_add(ttl.saturating_mul(1000)))
+}
+
+fn clean_file_name(name: &str) -> String {
+    let mut name = name.trim().to_string();
+    if name.is_empty() {
+        name = "unnamed".to_string();
+    }
+    truncate_name(&mut name);
+    name
+}
+
+/// 打包成员的归档内路径：统一为 `/` 分隔，去掉空段、`.` 与 `..`，防止解压越界
+fn clean_member_path(name: &str) -> String {
+    let mut path = name
+        .split(['/', '\\'])
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty() && *segment != "." && *segment != "..")
+        .collect::<Vec<_>>()
+        .join("/");
+    if path.is_empty() {
+        path = "unnamed".to_string();
     }
+    truncate_name(&mut path);
+    path
 }
+
+fn truncate_name(name: &mut String) {
+    while name.len() > MAX_FILE_NAME_BYTES {
+        name.pop();
+    }
+}
+
 /// 将持有方发来的二进制分块路由到在途传输；通道满时阻塞形成背压
 pub async fn route_chunk(conn_id: u64, bytes: Bytes) {
     let tx = match ACTIVE_TRANSFERS.get(&conn_id) {
@@ -152,13 +482,41 @@ pub async fn route_chunk(conn_id: u64, bytes: Bytes) {
 
 /// 持有方连接断开：清掉它的全部 offer 并中止在途传输
 pub async fn holder_disconnected(room_id: &str, conn_id: u64) {
-    FILE_OFFERS.retain(|_, offer| offer.conn_id != conn_id);
+    let mut removed = Vec::new();
+    FILE_OFFERS.retain(|file_id, offer| {
+        if offer.conn_id == conn_id {
+            removed.push((file_id.clone(), offer.room_id.clone()));
+            false
+        } else {
+            true
+        }
+    });
     if let Some((_, tx)) = ACTIVE_TRANSFERS.remove(&conn_id) {
         let _ = tx.send(TransferEvent::Aborted).await;
     }
+    for (file_id, offer_room) in removed {
+        notify_offer_removed(&offer_room, &file_id, "holder_disconnected").await;
+    }
     tracing::debug!("file holder gone: room={room_id} conn={conn_id}");
 }
 
+/// 移除单个 offer 并通知房间；offer 不存在时返回 false
+pub(crate) async fn remove_offer(file_id: &str, reason: &str) -> bool {
+    let Some((_, offer)) = FILE_OFFERS.remove(file_id) else {
+        return false;
+    };
+    notify_offer_removed(&offer.room_id, file_id, reason).await;
+    true
+}
+
+async fn notify_offer_removed(room_id: &str, file_id: &str, reason: &str) {
+    broadcast_control(
+        room_id,
+        &json!({"op": "offer_removed", "fileId": file_id, "reason": reason}),
+    )
+    .await;
+}
+
 pub(crate) async fn finish_transfer(conn_id: u64, event: TransferEvent) {
     if let Some((_, tx)) = ACTIVE_TRANSFERS.remove(&conn_id) {
         let _ = tx.send(event).await;
@@ -181,6 +539,118 @@ async fn send_control(room_id: &str, conn_id: u64, value: &serde_json::Value) ->
         .is_ok()
 }
 
+/// 向房间内全部广播连接发送控制消息（binary JSON 帧）
+async fn broadcast_control(room_id: &str, value: &serde_json::Value) {
+    let msg = Message::binary(value.to_string().into_bytes());
+    let users_map = BROADCAST_USERS.read().await;
+    if let Some(connections) = users_map.get(room_id) {
+        for connection in connections.iter() {
+            let _ = connection.sender.send(Ok(msg.clone()));
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// offer 对外暴露的元数据（列表接口与控制消息共用）
+fn offer_json(file_id: &str, offer: &Offer) -> serde_json::Value {
+    let mut value = json!({
+        "fileId": file_id,
+        "name": offer.name,
+        "size": offer.size,
+        "mime": offer.mime,
+        "connId": offer.conn_id,
+        "createdAt": offer.created_at,
+        "expiresAt": offer.expires_at,
+        "protected": offer.access.password.is_some(),
+        "signedOnly": offer.access.signed_only,
+        "maxDownloads": offer.access.max_downloads,
+        "downloads": offer.downloads,
+    });
+    if offer.is_bundle() {
+        value["members"] = offer
+            .members
+            .iter()
+            .map(|m| json!({"name": m.name, "size": m.size}))
+            .collect();
+    }
+    value
+}
+
+/// 房间内全部存活 offer，按登记时间排序；持有方已不在线的 offer 顺带清掉
+pub(crate) async fn room_offers(room_id: &str) -> Vec<serde_json::Value> {
+    let mut offers: Vec<(String, Offer)> = FILE_OFFERS
+        .iter()
+        .filter(|entry| entry.value().room_id == room_id)
+        .map(|entry| (entry.key().clone(), entry.value().clone()))
+        .collect();
+
+    let live_conns: Vec<u64> = {
+        let users_map = BROADCAST_USERS.read().await;
+        users_map
+            .get(room_id)
+            .map(|connections| connections.iter().map(|c| c.connection_id).collect())
+            .unwrap_or_default()
+    };
+    let now = now_millis();
+    let mut stale = Vec::new();
+    offers.retain(|(file_id, offer)| {
+        let reason = if !live_conns.contains(&offer.conn_id) {
+            "holder_disconnected"
+        } else if offer.is_expired(now) {
+            "expired"
+        } else {
+            return true;
+        };
+        stale.push((file_id.clone(), reason));
+        false
+    });
+    for (file_id, reason) in stale {
+        remove_offer(&file_id, reason).await;
+    }
+
+    offers.sort_by(|a, b| a.1.created_at.cmp(&b.1.created_at).then_with(|| a.0.cmp(&b.0)));
+    offers
+        .iter()
+        .map(|(file_id, offer)| offer_json(file_id, offer))
+        .collect()
+}
+
+/// 清掉 `now` 时刻已过期的 offer 并通知房间，返回清理数量
+pub(crate) async fn sweep_expired_offers(now: u64) -> usize {
+    let expired: Vec<String> = FILE_OFFERS
+        .iter()
+        .filter(|entry| entry.value().is_expired(now))
+        .map(|entry| entry.key().clone())
+        .collect();
+    let mut removed = 0;
+    for file_id in expired {
+        if remove_offer(&file_id, "expired").await {
+            tracing::info!("file offer expired: file={file_id}");
+            removed += 1;
+        }
+    }
+    removed
+}
+
+/// 后台定时清理过期 offer
+pub fn spawn_offer_sweeper() {
+    tokio::task::spawn(async {
+        let mut sweep_interval = tokio::time::interval(OFFER_SWEEP_INTERVAL);
+        loop {
+            sweep_interval.tick().await;
+            sweep_expired_offers(now_millis()).await;
+            let now = Instant::now();
+            ROOM_BANDWIDTH.retain(|_, free_at| *free_at > now);
+        }
+    });
+}
+
 async fn holder_alive(offer: &Offer) -> bool {
     let users_map = BROADCAST_USERS.read().await;
     users_map
@@ -195,17 +665,34 @@ pub async fn status(req: &mut Request, res: &mut Response) {
         Some(entry) => {
             let offer = entry.value().clone();
             drop(entry);
-            let alive = holder_alive(&offer).await;
-            if !alive {
-                FILE_OFFERS.remove(&file_id);
+            if offer.is_expired(now_millis()) {
+                remove_offer(&file_id, "expired").await;
+                false
+            } else {
+                let alive = holder_alive(&offer).await;
+                if !alive {
+                    remove_offer(&file_id, "holder_disconnected").await;
+                }
+                alive
             }
-            alive
         }
         None => false,
     };
     res.render(Json(json!({ "available": available })));
 }
 
+#[handler]
+pub async fn list(req: &mut Request, res: &mut Response) {
+    let room_id = req.query::<String>("room").unwrap_or_default();
+    if room_id.is_empty() {
+        res.status_code(StatusCode::BAD_REQUEST);
+        res.render(Json(json!({"error": "missing room"})));
+        return;
+    }
+    let files = room_offers(&room_id).await;
+    res.render(Json(json!({ "files": files })));
+}
+
 #[handler]
 pub async fn download(req: &mut Request, res: &mut Response) {
     let file_id = req.param::<String>("file_id").unwrap_or_default();
@@ -221,13 +708,26 @@ pub async fn download(req: &mut Request, res: &mut Response) {
         return;
     };
 
+    if offer.is_expired(now_millis()) {
+        remove_offer(&file_id, "expired").await;
+        res.status_code(StatusCode::GONE);
+        res.render(Json(json!({"error": "file offer expired"})));
+        return;
+    }
+
     if !holder_alive(&offer).await {
-        FILE_OFFERS.remove(&file_id);
+        remove_offer(&file_id, "holder_disconnected").await;
         res.status_code(StatusCode::GONE);
         res.render(Json(json!({"error": "file holder is offline"})));
         return;
     }
 
+    if let Err((code, message)) = check_access(req, &file_id, &offer) {
+        res.status_code(code);
+        res.render(Json(json!({ "error": message })));
+        return;
+    }
+
     let Some(rx) = try_start_transfer(offer.conn_id) else {
         res.status_code(StatusCode::CONFLICT);
         res.render(Json(
@@ -236,10 +736,23 @@ pub async fn download(req: &mut Request, res: &mut Response) {
         return;
     };
 
+    let Some(remaining) = take_download(&file_id) else {
+        ACTIVE_TRANSFERS.remove(&offer.conn_id);
+        res.status_code(StatusCode::GONE);
+        res.render(Json(json!({"error": "download limit reached"})));
+        return;
+    };
+
+    // 打包下载先拉第一个成员，其余成员在转发任务里依次拉取
+    let pull_id = offer
+        .members
+        .first()
+        .map(|m| m.file_id.clone())
+        .unwrap_or_else(|| file_id.clone());
     if !send_control(
         &offer.room_id,
         offer.conn_id,
-        &json!({"op": "pull", "fileId": file_id}),
+        &json!({"op": "pull", "fileId": pull_id}),
     )
     .await
     {
@@ -257,10 +770,13 @@ pub async fn download(req: &mut Request, res: &mut Response) {
             .parse()
             .unwrap_or_else(|_| "application/octet-stream".parse().unwrap()),
     );
-    headers.insert(
-        salvo::http::header::CONTENT_LENGTH,
-        salvo::http::HeaderValue::from(offer.size),
-    );
+    // 打包归档边拉边编码，总长度事先未知，走分块传输
+    if !offer.is_bundle() {
+        headers.insert(
+            salvo::http::header::CONTENT_LENGTH,
+            salvo::http::HeaderValue::from(offer.size),
+        );
+    }
     headers.insert(
         salvo::http::header::CONTENT_DISPOSITION,
         format!(
@@ -275,13 +791,174 @@ pub async fn download(req: &mut Request, res: &mut Response) {
     res.status_code(StatusCode::OK);
     res.body(body);
 
-    tokio::spawn(forward_transfer(
-        offer.room_id.clone(),
-        offer.conn_id,
-        offer.name.clone(),
-        rx,
-        body_tx,
-    ));
+    if remaining == Some(0) {
+        remove_offer(&file_id, "download_limit").await;
+    }
+
+    if offer.is_bundle() {
+        tokio::spawn(forward_bundle(offer, rx, body_tx));
+    } else {
+        tokio::spawn(forward_transfer(
+            offer.room_id.clone(),
+            offer.conn_id,
+            offer.name.clone(),
+            offer.size,
+            rx,
+            body_tx,
+        ));
+    }
+}
+
+/// 校验签名链接或下载口令；有效签名可替代口令
+fn check_access(
+    req: &Request,
+    file_id: &str,
+    offer: &Offer,
+) -> Result<(), (StatusCode, &'static str)> {
+    let signature = req.query::<String>("sig");
+    if let Some(signature) = signature {
+        let expires = req.query::<u64>("expires").unwrap_or_default();
+        return if links::verify(file_id, expires, &signature, now_millis() / 1000) {
+            Ok(())
+        } else {
+            Err((StatusCode::FORBIDDEN, "invalid or expired link"))
+        };
+    }
+    if offer.access.signed_only {
+        return Err((StatusCode::FORBIDDEN, "signed link required"));
+    }
+    if let Some(expected) = &offer.access.password {
+        let provided = req
+            .header::<String>(PASSWORD_HEADER)
+            .or_else(|| req.query::<String>("password"))
+            .unwrap_or_default();
+        if !links::constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+            return Err((StatusCode::UNAUTHORIZED, "password required"));
+        }
+    }
+    Ok(())
+}
+
+/// 占用一次下载次数；返回剩余次数（不限时为 `Some(None)`），已用尽或 offer 不存在时返回 None
+pub(crate) fn take_download(file_id: &str) -> Option<Option<u32>> {
+    let mut entry = FILE_OFFERS.get_mut(file_id)?;
+    let offer = entry.value_mut();
+    if let Some(max) = offer.access.max_downloads
+        && offer.downloads >= max
+    {
+        return None;
+    }
+    offer.downloads += 1;
+    Some(
+        offer
+            .access
+            .max_downloads
+            .map(|max| max.saturating_sub(offer.downloads)),
+    )
+}
+
+/// 单次传输的结束方式
+#[derive(Debug, PartialEq, Eq)]
+enum PumpEnd {
+    Done,
+    ReceiverGone,
+    Aborted,
+    Unexpected,
+    IdleTimeout,
+    /// `on_chunk` 拒绝了分块
+    Rejected,
+}
+
+/// 把一次传输的分块泵进下载响应，直到持有方结束、出错或 `on_chunk` 返回 false；
+/// 配置了房间带宽上限时按上限节流
+async fn pump_transfer(
+    room_id: &str,
+    rx: &mut mpsc::Receiver<TransferEvent>,
+    body_tx: &mut BodySender,
+    mut on_chunk: impl FnMut(&Bytes) -> bool,
+) -> PumpEnd {
+    let bandwidth = limits().room_bandwidth.filter(|rate| *rate > 0);
+    loop {
+        match timeout(CHUNK_IDLE_TIMEOUT, rx.recv()).await {
+            Ok(Some(TransferEvent::Chunk(bytes))) => {
+                if !on_chunk(&bytes) {
+                    return PumpEnd::Rejected;
+                }
+                if let Some(rate) = bandwidth {
+                    let delay = reserve_bandwidth(room_id, bytes.len(), rate, Instant::now());
+                    if !delay.is_zero() {
+                        tokio::time::sleep(delay).await;
+                    }
+                }
+                if body_tx.send_data(bytes).await.is_err() {
+                    return PumpEnd::ReceiverGone;
+                }
+            }
+            Ok(Some(TransferEvent::Done)) => return PumpEnd::Done,
+            Ok(Some(TransferEvent::Aborted)) => return PumpEnd::Aborted,
+            Ok(None) => return PumpEnd::Unexpected,
+            Err(_) => return PumpEnd::IdleTimeout,
+        }
+    }
+}
+
+/// 为房间预约 `len` 字节的发送时段，返回本分块需要等待的时长
+pub(crate) fn reserve_bandwidth(room_id: &str, len: usize, rate: u64, now: Instant) -> Duration {
+    let cost = Duration::from_secs_f64(len as f64 / rate as f64);
+    let mut free_at = ROOM_BANDWIDTH.entry(room_id.to_string()).or_insert(now);
+    let start = (*free_at).max(now);
+    *free_at = start + cost;
+    start - now
+}
+
+/// 持有方发送的字节数超过声明大小时拒绝后续分块
+pub(crate) fn declared_size_guard(declared: u64) -> impl FnMut(&Bytes) -> bool {
+    let mut received = 0u64;
+    move |bytes| {
+        received = received.saturating_add(bytes.len() as u64);
+        received <= declared
+    }
+}
+
+/// 记录非正常结束并据此终止下载响应；下载方中断或分块被拒时通知持有方停止
+async fn report_pump_end(
+    room_id: &str,
+    conn_id: u64,
+    name: &str,
+    end: PumpEnd,
+    body_tx: &mut BodySender,
+) {
+    match end {
+        PumpEnd::Done => return,
+        PumpEnd::ReceiverGone => {
+            tracing::info!(
+                "download cancelled by receiver: room={room_id} conn={conn_id} name={name}"
+            );
+        }
+        PumpEnd::Aborted => {
+            tracing::info!(
+                "file transfer aborted by holder: room={room_id} conn={conn_id} name={name}"
+            );
+            body_tx.send_error(std::io::Error::other("transfer aborted by holder"));
+        }
+        PumpEnd::Unexpected => {
+            tracing::warn!(
+                "file transfer ended without done: room={room_id} conn={conn_id} name={name}"
+            );
+            body_tx.send_error(std::io::Error::other("transfer ended unexpectedly"));
+        }
+        PumpEnd::IdleTimeout => {
+            tracing::warn!("file transfer idle timeout: room={room_id} conn={conn_id} name={name}");
+            body_tx.send_error(std::io::Error::other("transfer idle timeout"));
+        }
+        PumpEnd::Rejected => {
+            tracing::warn!("file transfer rejected: room={room_id} conn={conn_id} name={name}");
+            body_tx.send_error(std::io::Error::other("transfer exceeds allowed size"));
+        }
+    }
+    if matches!(end, PumpEnd::ReceiverGone | PumpEnd::Rejected) {
+        send_control(room_id, conn_id, &json!({"op": "cancel"})).await;
+    }
 }
 
 /// 把传输事件泵进下载响应；出口处清理传输槽，下载方中断时通知持有方停止
@@ -289,46 +966,99 @@ async fn forward_transfer(
     room_id: String,
     conn_id: u64,
     name: String,
+    size: u64,
     mut rx: mpsc::Receiver<TransferEvent>,
     mut body_tx: BodySender,
 ) {
-    let mut receiver_gone = false;
+    let end = pump_transfer(&room_id, &mut rx, &mut body_tx, declared_size_guard(size)).await;
+    ACTIVE_TRANSFERS.remove(&conn_id);
+    report_pump_end(&room_id, conn_id, &name, end, &mut body_tx).await;
+}
+
+/// 等待持有方传输槽空出（其他下载可能插在成员之间），超时返回 None
+async fn wait_transfer_slot(conn_id: u64) -> Option<mpsc::Receiver<TransferEvent>> {
+    let deadline = tokio::time::Instant::now() + CHUNK_IDLE_TIMEOUT;
     loop {
-        match timeout(CHUNK_IDLE_TIMEOUT, rx.recv()).await {
-            Ok(Some(TransferEvent::Chunk(bytes))) => {
-                if body_tx.send_data(bytes).await.is_err() {
-                    receiver_gone = true;
-                    break;
+        if let Some(rx) = try_start_transfer(conn_id) {
+            return Some(rx);
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return None;
+        }
+        tokio::time::sleep(SLOT_RETRY_INTERVAL).await;
+    }
+}
+
+/// 依次拉取打包成员，边收边编码为 stored ZIP 写入下载响应
+async fn forward_bundle(
+    offer: Offer,
+    first_rx: mpsc::Receiver<TransferEvent>,
+    mut body_tx: BodySender,
+) {
+    let Offer {
+        room_id,
+        conn_id,
+        name,
+        members,
+        ..
+    } = offer;
+    let mut zip = ZipStream::default();
+    let mut next_rx = Some(first_rx);
+
+    for member in &members {
+        let mut rx = match next_rx.take() {
+            Some(rx) => rx,
+            None => {
+                let Some(rx) = wait_transfer_slot(conn_id).await else {
+                    tracing::warn!(
+                        "bundle transfer stalled waiting for holder: room={room_id} conn={conn_id} name={name}"
+                    );
+                    body_tx.send_error(std::io::Error::other("transfer idle timeout"));
+                    return;
+                };
+                if !send_control(
+                    &room_id,
+                    conn_id,
+                    &json!({"op": "pull", "fileId": member.file_id}),
+                )
+                .await
+                {
+                    ACTIVE_TRANSFERS.remove(&conn_id);
+                    body_tx.send_error(std::io::Error::other("file holder is offline"));
+                    return;
                 }
+                rx
             }
-            Ok(Some(TransferEvent::Done)) => break,
-            Ok(Some(TransferEvent::Aborted)) => {
-                tracing::info!(
-                    "file transfer aborted by holder: room={room_id} conn={conn_id} name={name}"
-                );
-                body_tx.send_error(std::io::Error::other("transfer aborted by holder"));
-                break;
-            }
-            Ok(None) => {
-                tracing::warn!(
-                    "file transfer ended without done: room={room_id} conn={conn_id} name={name}"
-                );
-                body_tx.send_error(std::io::Error::other("transfer ended unexpectedly"));
-                break;
-            }
-            Err(_) => {
-                tracing::warn!(
-                    "file transfer idle timeout: room={room_id} conn={conn_id} name={name}"
-                );
-                body_tx.send_error(std::io::Error::other("transfer idle timeout"));
-                break;
+        };
+
+        if body_tx.send_data(zip.start_entry(&member.name)).await.is_err() {
+            ACTIVE_TRANSFERS.remove(&conn_id);
+            report_pump_end(&room_id, conn_id, &name, PumpEnd::ReceiverGone, &mut body_tx).await;
+            return;
+        }
+        let mut guard = declared_size_guard(member.size);
+        let end = pump_transfer(&room_id, &mut rx, &mut body_tx, |bytes| {
+            guard(bytes) && zip.entry_data(bytes)
+        })
+        .await;
+        if end != PumpEnd::Done {
+            // done/abort 时持有方已释放槽位，此处不能误删下一次传输占用的槽
+            if end != PumpEnd::Aborted {
+                ACTIVE_TRANSFERS.remove(&conn_id);
             }
+            report_pump_end(&room_id, conn_id, &name, end, &mut body_tx).await;
+            return;
+        }
+        if body_tx.send_data(zip.finish_entry()).await.is_err() {
+            tracing::info!(
+                "download cancelled by receiver: room={room_id} conn={conn_id} name={name}"
+            );
+            return;
         }
     }
-    ACTIVE_TRANSFERS.remove(&conn_id);
-    if receiver_gone {
+
+    if body_tx.send_data(zip.finish()).await.is_err() {
         tracing::info!("download cancelled by receiver: room={room_id} conn={conn_id} name={name}");
-        send_control(&room_id, conn_id, &json!({"op": "cancel"})).await;
     }
 }
 
@@ -0,0 +1,76 @@
+use std::sync::OnceLock;
+
+use hmac::{Hmac, Mac};
+use nanoid::nanoid;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 签名下载链接的密钥；未配置时进程启动后随机生成，重启即失效
+static LINK_SECRET: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// 启动时设置一次签名密钥（多实例部署需共享同一密钥）
+pub fn init_link_secret(secret: String) {
+    let _ = LINK_SECRET.set(secret.into_bytes());
+}
+
+fn link_secret() -> &'static [u8] {
+    LINK_SECRET.get_or_init(|| nanoid!(32).into_bytes())
+}
+
+fn mac(file_id: &str, expires: u64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(link_secret()).expect("HMAC accepts any key length");
+    mac.update(file_id.as_bytes());
+    mac.update(b".");
+    mac.update(expires.to_string().as_bytes());
+    mac
+}
+
+/// 为 `file_id` 生成在 `expires`（Unix 秒）前有效的签名（hex）
+pub fn sign(file_id: &str, expires: u64) -> String {
+    to_hex(&mac(file_id, expires).finalize().into_bytes())
+}
+
+/// 校验签名且未过期；比较为常量时间
+pub fn verify(file_id: &str, expires: u64, signature: &str, now_secs: u64) -> bool {
+    if expires <= now_secs {
+        return false;
+    }
+    let Some(signature) = from_hex(signature) else {
+        return false;
+    };
+    mac(file_id, expires).verify_slice(&signature).is_ok()
+}
+
+/// 常量时间比较，避免通过耗时猜测口令
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn to_hex(data: &[u8]) -> String {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let mut s = String::with_capacity(data.len() * 2);
+    for &b in data {
+        s.push(HEX[(b >> 4) as usize] as char);
+        s.push(HEX[(b & 0x0f) as usize] as char);
+    }
+    s
+}
+
+fn from_hex(input: &str) -> Option<Vec<u8>> {
+    if !input.len().is_multiple_of(2) {
+        return None;
+    }
+    input
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let hi = (pair[0] as char).to_digit(16)?;
+            let lo = (pair[1] as char).to_digit(16)?;
+            Some((hi * 16 + lo) as u8)
+        })
+        .collect()
+}
@@ -8,7 +8,9 @@ use tracing_subscriber::EnvFilter;
 
 mod broadcast;
 mod files;
+mod links;
 mod single;
+mod zip;
 
 #[cfg(test)]
 mod tests;
@@ -24,6 +26,27 @@ struct Cli {
     /// The port to listen on.
     #[arg(short, long, default_value_t = 5800)]
     port: u16,
+
+    /// Maximum size in bytes of a single offered file (bundle members count individually).
+    #[arg(long)]
+    max_file_size: Option<u64>,
+
+    /// Maximum total bytes of all file offers in one room.
+    #[arg(long)]
+    max_room_bytes: Option<u64>,
+
+    /// Maximum total bytes of all file offers from one connection.
+    #[arg(long)]
+    max_connection_bytes: Option<u64>,
+
+    /// Download bandwidth ceiling per room, in bytes per second.
+    #[arg(long)]
+    room_bandwidth: Option<u64>,
+
+    /// Secret for signing download links. A random one is generated when omitted,
+    /// so links stop working after a restart.
+    #[arg(long)]
+    link_secret: Option<String>,
 }
 
 #[handler]
@@ -66,6 +89,17 @@ async fn main() {
         )
         .init();
 
+    files::init_limits(files::FileLimits {
+        max_file_size: cli.max_file_size,
+        max_room_bytes: cli.max_room_bytes,
+        max_connection_bytes: cli.max_connection_bytes,
+        room_bandwidth: cli.room_bandwidth,
+    });
+    if let Some(secret) = cli.link_secret {
+        links::init_link_secret(secret);
+    }
+    files::spawn_offer_sweeper();
+
     // Bind server to port 5800
     let acceptor = TcpListener::new(format!("0.0.0.0:{}", cli.port))
         .bind()
@@ -81,6 +115,7 @@ async fn main() {
         .push(Router::with_path("broad/pub").post(broadcast::broadcast_publish))
         .push(Router::with_path("files/download/{file_id}").get(files::download))
         .push(Router::with_path("files/status/{file_id}").get(files::status))
+        .push(Router::with_path("files/list").get(files::list))
         .push(Router::with_path("connections").goal(connections))
         .push(Router::with_path("health").goal(health))
         .push(Router::with_path("version").goal(version))
@@ -5,10 +5,10 @@ use salvo::prelude::*;
 use salvo::websocket::{Message, WebSocket, WebSocketUpgrade};
 
 use bytes::Bytes;
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use futures_util::{FutureExt, StreamExt};
 use nanoid::nanoid;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::{Duration, interval, timeout};
 use tokio_stream::wrappers::UnboundedReceiverStream;
@@ -26,6 +26,90 @@ type CallbackChannels = DashMap<String, VecDeque<(String, oneshot::Sender<Bytes>
 
 pub static ONLINE_USERS: LazyLock<Users> = LazyLock::new(Users::default);
 pub static CALLBACK_CHANNELS: LazyLock<CallbackChannels> = LazyLock::new(CallbackChannels::default);
+/// Connections opened with `tagged=true`, by connection id.
+pub static TAGGED_CONNS: LazyLock<DashSet<String>> = LazyLock::new(DashSet::new);
+
+/// Header of a frame sent to, or a reply received from, a tagged connection.
+/// On the wire it is one JSON line followed by the payload, in a binary frame.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameHeader {
+    /// `shot` or `ping_pong`; absent on replies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// `text` or `binary`; absent on replies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "type")]
+    pub msg_type: Option<String>,
+    /// Set on ping_pong requests; a reply carrying it answers that request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Wraps a published message for a tagged connection.
+pub fn tag_message(msg: &Message, request_id: Option<&str>) -> Message {
+    let mode = if request_id.is_some() {
+        "ping_pong"
+    } else {
+        "shot"
+    };
+    let msg_type = if msg.is_text() { "text" } else { "binary" };
+    let header = FrameHeader {
+        mode: Some(mode.to_string()),
+        msg_type: Some(msg_type.to_string()),
+        request_id: request_id.map(str::to_string),
+    };
+    let mut frame = serde_json::to_vec(&header).unwrap_or_default();
+    frame.push(b'\n');
+    frame.extend_from_slice(msg.as_bytes());
+    Message::binary(frame)
+}
+
+/// Splits a tagged frame into its header and payload.
+pub fn untag_frame(data: &[u8]) -> Option<(FrameHeader, &[u8])> {
+    let newline = data.iter().position(|&b| b == b'\n')?;
+    let header = serde_json::from_slice(&data[..newline]).ok()?;
+    Some((header, &data[newline + 1..]))
+}
+
+/// Hands a subscriber frame to the publisher waiting for it. A tagged reply
+/// goes to the request it names, or nowhere if that one timed out; other
+/// frames answer the oldest waiting request.
+pub fn resolve_reply(uid: &str, tagged: bool, data: Bytes) {
+    let Some(mut entry) = CALLBACK_CHANNELS.get_mut(uid) else {
+        return;
+    };
+    let (position, payload) = if tagged {
+        let Some((header, payload)) = untag_frame(&data) else {
+            tracing::warn!("Dropping untagged reply from tagged user {}", uid);
+            return;
+        };
+        let Some(request_id) = header.request_id else {
+            tracing::warn!("Dropping reply without requestId from user {}", uid);
+            return;
+        };
+        let Some(position) = entry.iter().position(|(id, _)| *id == request_id) else {
+            tracing::debug!(
+                "Reply for finished request {} from user {}",
+                request_id,
+                uid
+            );
+            return;
+        };
+        (position, data.slice_ref(payload))
+    } else {
+        (0, data)
+    };
+    if let Some((_id, tx)) = entry.remove(position)
+        && let Err(e) = tx.send(payload)
+    {
+        tracing::error!(
+            "Failed to send message to callback channel for user {}: {:?}",
+            uid,
+            e
+        );
+    }
+}
 
 #[handler]
 pub async fn user_connected(req: &mut Request, res: &mut Response) -> Result<(), StatusError> {
@@ -35,14 +119,18 @@ pub async fn user_connected(req: &mut Request, res: &mut Response) -> Result<(),
     if string_uid.is_empty() {
         return Err(StatusError::bad_request().detail("'id' query parameter cannot be empty"));
     }
+    let tagged = req.query::<bool>("tagged").unwrap_or(false);
     WebSocketUpgrade::new()
-        .upgrade(req, res, |ws| handle_socket(ws, string_uid))
+        .upgrade(req, res, move |ws| handle_socket(ws, string_uid, tagged))
         .await
 }
 
-async fn handle_socket(ws: WebSocket, my_id: String) {
+async fn handle_socket(ws: WebSocket, my_id: String, tagged: bool) {
     tracing::info!("new single user: {}", my_id);
     let conn_id = nanoid!();
+    if tagged {
+        TAGGED_CONNS.insert(conn_id.clone());
+    }
 
     let (user_ws_tx, mut user_ws_rx) = ws.split();
 
@@ -91,17 +179,11 @@ async fn handle_socket(ws: WebSocket, my_id: String) {
                     tracing::debug!("Received pong from user: {}, ignoring", my_id);
                     continue;
                 }
-                let data: Bytes = msg.as_bytes().to_vec().into();
-                if let Some(mut entry) = CALLBACK_CHANNELS.get_mut(&my_id)
-                    && let Some((_id, tx)) = entry.pop_front()
-                    && let Err(e) = tx.send(data)
-                {
-                    tracing::error!(
-                        "Failed to send message to callback channel for user {}: {:?}",
-                        my_id,
-                        e
-                    );
+                if msg.is_close() || msg.is_ping() {
+                    continue;
                 }
+                let data: Bytes = msg.as_bytes().to_vec().into();
+                resolve_reply(&my_id, tagged, data);
             }
             Err(e) => {
                 tracing::warn!("WebSocket error for user {}: {:?}", my_id, e);
@@ -115,6 +197,7 @@ async fn handle_socket(ws: WebSocket, my_id: String) {
 
 pub async fn user_disconnected(my_id: String, conn_id: String) {
     tracing::info!("subscriber disconnected: user {}, conn {}", my_id, conn_id);
+    TAGGED_CONNS.remove(&conn_id);
     if let Some(user_conns) = ONLINE_USERS.get_mut(&my_id) {
         user_conns.remove(&conn_id);
         if user_conns.is_empty() {
@@ -169,7 +252,12 @@ pub async fn publish_message(req: &mut Request, res: &mut Response) {
 
                 let mut disconnected_conns = Vec::new();
                 for conn in user_conns.iter() {
-                    if conn.value().send(Ok(msg.clone())).is_err() {
+                    let msg = if TAGGED_CONNS.contains(conn.key()) {
+                        tag_message(&msg, None)
+                    } else {
+                        msg.clone()
+                    };
+                    if conn.value().send(Ok(msg)).is_err() {
                         disconnected_conns.push(conn.key().clone());
                     }
                 }
@@ -215,7 +303,12 @@ pub async fn publish_message(req: &mut Request, res: &mut Response) {
                 let mut disconnected_conns = Vec::new();
                 let mut sent = false;
                 for conn in user_conns.iter() {
-                    if conn.value().send(Ok(msg.clone())).is_ok() {
+                    let msg = if TAGGED_CONNS.contains(conn.key()) {
+                        tag_message(&msg, Some(&id))
+                    } else {
+                        msg.clone()
+                    };
+                    if conn.value().send(Ok(msg)).is_ok() {
                         sent = true;
                         break;
                     } else {
@@ -2,10 +2,16 @@
 mod test {
     use crate::broadcast::{BROADCAST_USERS, Connection};
     use crate::files::{
-        self, ACTIVE_TRANSFERS, FILE_OFFERS, TransferEvent, handle_client_op, holder_disconnected,
-        route_chunk, try_start_transfer,
+        self, ACTIVE_TRANSFERS, FILE_OFFERS, FileLimits, TransferEvent, check_offer_limits,
+        declared_size_guard, handle_client_op, holder_disconnected, reserve_bandwidth,
+        room_offers, route_chunk, sweep_expired_offers, take_download, try_start_transfer,
     };
-    use crate::single::{CALLBACK_CHANNELS, Mode, ONLINE_USERS, user_disconnected};
+    use crate::links;
+    use crate::single::{
+        CALLBACK_CHANNELS, Mode, ONLINE_USERS, resolve_reply, tag_message, untag_frame,
+        user_disconnected,
+    };
+    use crate::zip::ZipStream;
     use bytes::Bytes;
     use std::time::Duration;
     use tokio::sync::mpsc;
@@ -249,6 +255,54 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_tag_message_round_trip() {
+        // 带标记的帧：一行 JSON 头 + 原始负载
+        let tagged = tag_message(&salvo::websocket::Message::text("hello"), Some("req1"));
+        let (header, payload) = untag_frame(tagged.as_bytes()).expect("应能解析帧头");
+        assert_eq!(header.mode.as_deref(), Some("ping_pong"));
+        assert_eq!(header.msg_type.as_deref(), Some("text"));
+        assert_eq!(header.request_id.as_deref(), Some("req1"));
+        assert_eq!(payload, b"hello");
+
+        // shot 消息没有 requestId，二进制负载原样保留（包括换行）
+        let tagged = tag_message(&salvo::websocket::Message::binary(vec![0x0a, 0xff]), None);
+        let (header, payload) = untag_frame(tagged.as_bytes()).unwrap();
+        assert_eq!(header.mode.as_deref(), Some("shot"));
+        assert_eq!(header.msg_type.as_deref(), Some("binary"));
+        assert_eq!(header.request_id, None);
+        assert_eq!(payload, &[0x0a, 0xff]);
+
+        assert!(untag_frame(b"no header").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_reply_routing() {
+        let user_id = "test_resolve_reply_user";
+        let (first_tx, first_rx) = tokio::sync::oneshot::channel::<Bytes>();
+        let (second_tx, second_rx) = tokio::sync::oneshot::channel::<Bytes>();
+        {
+            let mut entry = CALLBACK_CHANNELS.entry(user_id.to_string()).or_default();
+            entry.push_back(("first".to_string(), first_tx));
+            entry.push_back(("second".to_string(), second_tx));
+        }
+
+        // 带标记的回复按 requestId 匹配，而不是先进先出
+        resolve_reply(user_id, true, Bytes::from_static(b"{\"requestId\":\"second\"}\nanswer"));
+        assert_eq!(second_rx.await.unwrap(), Bytes::from_static(b"answer"));
+
+        // 未知 requestId 和无帧头的回复被丢弃
+        resolve_reply(user_id, true, Bytes::from_static(b"{\"requestId\":\"gone\"}\nlate"));
+        resolve_reply(user_id, true, Bytes::from_static(b"untagged"));
+        assert_eq!(CALLBACK_CHANNELS.get(user_id).unwrap().len(), 1);
+
+        // 未标记的连接仍然回复最早的请求
+        resolve_reply(user_id, false, Bytes::from_static(b"plain"));
+        assert_eq!(first_rx.await.unwrap(), Bytes::from_static(b"plain"));
+
+        CALLBACK_CHANNELS.remove(user_id);
+    }
+
     // ========== Broadcast 模块测试 ==========
 
     #[tokio::test]
@@ -1069,6 +1123,399 @@ mod test {
         cleanup_room(room).await;
     }
 
+    #[tokio::test]
+    async fn test_list_offers_and_room_notifications() {
+        let room = "ft_room_list";
+        let holder = 9107u64;
+        let watcher = 9108u64;
+        let mut holder_rx = register_test_connection(room, holder).await;
+        let mut watcher_rx = register_test_connection(room, watcher).await;
+
+        handle_client_op(
+            room,
+            holder,
+            r#"{"op":"offer","name":"a.txt","size":5,"mime":"text/plain"}"#,
+        )
+        .await;
+        let ok = next_control(&mut holder_rx).await;
+        assert_eq!(ok["op"], "offer_ok");
+        let file_id = ok["fileId"].as_str().unwrap().to_string();
+
+        // 房间内其他连接收到 offer_added
+        let added = next_control(&mut watcher_rx).await;
+        assert_eq!(added["op"], "offer_added");
+        assert_eq!(added["file"]["fileId"], file_id);
+        assert_eq!(added["file"]["name"], "a.txt");
+        assert_eq!(added["file"]["size"], 5);
+        assert_eq!(added["file"]["mime"], "text/plain");
+        assert_eq!(added["file"]["connId"], holder);
+        assert!(added["file"]["createdAt"].as_u64().unwrap() > 0);
+
+        // HTTP 列表与 list_offers 指令返回同样内容
+        let files = room_offers(room).await;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0]["fileId"], file_id);
+
+        handle_client_op(room, watcher, r#"{"op":"list_offers"}"#).await;
+        let listed = next_control(&mut watcher_rx).await;
+        assert_eq!(listed["op"], "offers");
+        assert_eq!(listed["files"].as_array().unwrap().len(), 1);
+        assert_eq!(listed["files"][0]["fileId"], file_id);
+
+        // 持有方断开后，房间收到 offer_removed
+        holder_disconnected(room, holder).await;
+        let removed = next_control(&mut watcher_rx).await;
+        assert_eq!(removed["op"], "offer_removed");
+        assert_eq!(removed["fileId"], file_id);
+        assert!(room_offers(room).await.is_empty(), "断开后列表应为空");
+
+        cleanup_room(room).await;
+    }
+
+    #[tokio::test]
+    async fn test_room_offers_prunes_offline_holder() {
+        let room = "ft_room_prune";
+        let holder = 9109u64;
+        let watcher = 9110u64;
+        let _holder_rx = register_test_connection(room, holder).await;
+        let mut watcher_rx = register_test_connection(room, watcher).await;
+
+        handle_client_op(room, holder, r#"{"op":"offer","name":"b.bin","size":1}"#).await;
+        let added = next_control(&mut watcher_rx).await;
+        assert_eq!(added["op"], "offer_added");
+
+        // 模拟持有方连接已从房间移除但 offer 尚未清理
+        {
+            let mut users_map = BROADCAST_USERS.write().await;
+            users_map
+                .get_mut(room)
+                .unwrap()
+                .retain(|c| c.connection_id != holder);
+        }
+
+        assert!(room_offers(room).await.is_empty(), "离线持有方的 offer 不应列出");
+        let removed = next_control(&mut watcher_rx).await;
+        assert_eq!(removed["op"], "offer_removed");
+        assert_eq!(removed["fileId"], added["file"]["fileId"]);
+
+        cleanup_room(room).await;
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_offer() {
+        let room = "ft_room_withdraw";
+        let holder = 9111u64;
+        let other = 9112u64;
+        let mut holder_rx = register_test_connection(room, holder).await;
+        let mut other_rx = register_test_connection(room, other).await;
+
+        handle_client_op(room, holder, r#"{"op":"offer","name":"w.bin","size":3}"#).await;
+        let ok = next_control(&mut holder_rx).await;
+        let file_id = ok["fileId"].as_str().unwrap().to_string();
+        let _ = next_control(&mut holder_rx).await; // 消费 offer_added
+        let _ = next_control(&mut other_rx).await; // 消费 offer_added
+
+        // 非持有方不能撤回
+        handle_client_op(
+            room,
+            other,
+            &format!(r#"{{"op":"withdraw","fileId":"{file_id}"}}"#),
+        )
+        .await;
+        let err = next_control(&mut other_rx).await;
+        assert_eq!(err["op"], "error");
+        assert!(FILE_OFFERS.contains_key(&file_id), "offer 不应被他人撤回");
+
+        // 持有方撤回，房间收到 offer_removed
+        handle_client_op(
+            room,
+            holder,
+            &format!(r#"{{"op":"withdraw","fileId":"{file_id}"}}"#),
+        )
+        .await;
+        assert!(!FILE_OFFERS.contains_key(&file_id), "撤回后 offer 应被移除");
+        let removed = next_control(&mut other_rx).await;
+        assert_eq!(removed["op"], "offer_removed");
+        assert_eq!(removed["fileId"], file_id);
+        assert_eq!(removed["reason"], "withdrawn");
+
+        cleanup_room(room).await;
+    }
+
+    #[tokio::test]
+    async fn test_offer_ttl_expiry() {
+        let room = "ft_room_ttl";
+        let holder = 9113u64;
+        let mut rx = register_test_connection(room, holder).await;
+
+        handle_client_op(
+            room,
+            holder,
+            r#"{"op":"offer","name":"t.bin","size":1,"ttl":60}"#,
+        )
+        .await;
+        let ok = next_control(&mut rx).await;
+        let file_id = ok["fileId"].as_str().unwrap().to_string();
+        let added = next_control(&mut rx).await;
+        let created_at = added["file"]["createdAt"].as_u64().unwrap();
+        assert_eq!(added["file"]["expiresAt"], created_at + 60_000);
+
+        // 未到期时不清理
+        sweep_expired_offers(created_at + 59_000).await;
+        assert!(FILE_OFFERS.contains_key(&file_id), "未到期的 offer 应保留");
+
+        // 到期后清理并通知房间
+        sweep_expired_offers(created_at + 60_000).await;
+        assert!(!FILE_OFFERS.contains_key(&file_id), "到期的 offer 应被清理");
+        let removed = next_control(&mut rx).await;
+        assert_eq!(removed["op"], "offer_removed");
+        assert_eq!(removed["reason"], "expired");
+
+        cleanup_room(room).await;
+    }
+
+    #[tokio::test]
+    async fn test_offer_bundle_registers_members() {
+        let room = "ft_room_bundle";
+        let conn = 9114u64;
+        let mut rx = register_test_connection(room, conn).await;
+
+        handle_client_op(
+            room,
+            conn,
+            r#"{"op":"offer_bundle","offerId":"b1","name":"photos","files":[{"name":"a/1.png","size":10},{"name":"../../etc/passwd","size":5},{"name":"dir\\2.png","size":7}]}"#,
+        )
+        .await;
+
+        let ok = next_control(&mut rx).await;
+        assert_eq!(ok["op"], "bundle_ok");
+        assert_eq!(ok["offerId"], "b1");
+        let bundle_id = ok["fileId"].as_str().unwrap().to_string();
+        let files = ok["files"].as_array().unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0]["name"], "a/1.png");
+        assert_eq!(files[1]["name"], "etc/passwd", "应去掉越界路径段");
+        assert_eq!(files[2]["name"], "dir/2.png", "反斜杠应转为 /");
+        assert_ne!(files[0]["fileId"], files[1]["fileId"]);
+
+        let offer = FILE_OFFERS.get(&bundle_id).unwrap().value().clone();
+        assert!(offer.is_bundle());
+        assert_eq!(offer.name, "photos.zip");
+        assert_eq!(offer.size, 22);
+        assert_eq!(offer.mime, "application/zip");
+        assert_eq!(offer.members[0].file_id, files[0]["fileId"]);
+
+        let added = next_control(&mut rx).await;
+        assert_eq!(added["op"], "offer_added");
+        assert_eq!(added["file"]["members"].as_array().unwrap().len(), 3);
+
+        // 空打包被拒绝
+        handle_client_op(
+            room,
+            conn,
+            r#"{"op":"offer_bundle","name":"empty","files":[]}"#,
+        )
+        .await;
+        let err = next_control(&mut rx).await;
+        assert_eq!(err["op"], "error");
+
+        cleanup_room(room).await;
+    }
+
+    #[test]
+    fn test_zip_stream_layout() {
+        fn u16_at(buf: &[u8], at: usize) -> u16 {
+            u16::from_le_bytes([buf[at], buf[at + 1]])
+        }
+        fn u32_at(buf: &[u8], at: usize) -> u32 {
+            u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+        }
+
+        let mut zip = ZipStream::default();
+        let mut archive = Vec::new();
+        archive.extend_from_slice(&zip.start_entry("hello.txt"));
+        assert!(zip.entry_data(b"hel"));
+        assert!(zip.entry_data(b"lo"));
+        archive.extend_from_slice(b"hello");
+        let descriptor = zip.finish_entry();
+        archive.extend_from_slice(&descriptor);
+        let second_offset = archive.len();
+        archive.extend_from_slice(&zip.start_entry("dir/empty"));
+        archive.extend_from_slice(&zip.finish_entry());
+        archive.extend_from_slice(&zip.finish());
+
+        // 本地文件头 + 数据描述符（CRC32("hello") = 0x3610a686）
+        assert_eq!(u32_at(&archive, 0), 0x04034b50);
+        assert_eq!(u32_at(&descriptor, 0), 0x08074b50);
+        assert_eq!(u32_at(&descriptor, 4), 0x3610a686);
+        assert_eq!(u32_at(&descriptor, 8), 5);
+        assert_eq!(u32_at(&archive, second_offset), 0x04034b50);
+
+        // 目录结束记录：2 个成员，中央目录偏移与长度自洽
+        let eocd = archive.len() - 22;
+        assert_eq!(u32_at(&archive, eocd), 0x06054b50);
+        assert_eq!(u16_at(&archive, eocd + 10), 2);
+        let cd_size = u32_at(&archive, eocd + 12) as usize;
+        let cd_offset = u32_at(&archive, eocd + 16) as usize;
+        assert_eq!(cd_offset + cd_size, eocd);
+        assert_eq!(u32_at(&archive, cd_offset), 0x02014b50);
+        assert_eq!(u32_at(&archive, cd_offset + 16), 0x3610a686);
+        let second_cd = cd_offset + 46 + "hello.txt".len();
+        assert_eq!(u32_at(&archive, second_cd + 42) as usize, second_offset);
+    }
+
+    #[tokio::test]
+    async fn test_offer_size_limits() {
+        let room = "ft_room_quota";
+        let conn = 9115u64;
+        let other = 9116u64;
+        let mut rx = register_test_connection(room, conn).await;
+        let _other_rx = register_test_connection(room, other).await;
+
+        handle_client_op(room, conn, r#"{"op":"offer","name":"q.bin","size":600}"#).await;
+        let _ = next_control(&mut rx).await;
+
+        let limits = FileLimits {
+            max_file_size: Some(1000),
+            max_room_bytes: Some(1500),
+            max_connection_bytes: Some(1000),
+            room_bandwidth: None,
+        };
+        assert_eq!(check_offer_limits(&limits, room, conn, &[400]), Ok(()));
+        assert_eq!(
+            check_offer_limits(&limits, room, conn, &[1001]),
+            Err("file too large")
+        );
+        assert_eq!(
+            check_offer_limits(&limits, room, conn, &[300, 200]),
+            Err("connection quota exceeded"),
+            "打包成员合计计入连接配额"
+        );
+        assert_eq!(check_offer_limits(&limits, room, other, &[900]), Ok(()));
+        assert_eq!(
+            check_offer_limits(&limits, room, other, &[901]),
+            Err("room quota exceeded")
+        );
+        assert_eq!(
+            check_offer_limits(&FileLimits::default(), room, conn, &[u64::MAX]),
+            Ok(()),
+            "默认不限大小"
+        );
+
+        cleanup_room(room).await;
+    }
+
+    #[test]
+    fn test_declared_size_guard() {
+        let mut guard = declared_size_guard(5);
+        assert!(guard(&Bytes::from_static(b"abc")));
+        assert!(guard(&Bytes::from_static(b"de")));
+        assert!(!guard(&Bytes::from_static(b"f")), "超出声明大小应被拒绝");
+    }
+
+    #[test]
+    fn test_room_bandwidth_reservation() {
+        let room = "ft_room_bandwidth";
+        let now = tokio::time::Instant::now();
+        // 1000 B/s：第一个 500 字节立即发送，后续分块排在其后
+        assert_eq!(reserve_bandwidth(room, 500, 1000, now), Duration::ZERO);
+        assert_eq!(
+            reserve_bandwidth(room, 500, 1000, now),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            reserve_bandwidth(room, 1000, 1000, now + Duration::from_millis(200)),
+            Duration::from_millis(800)
+        );
+        // 其他房间互不影响
+        assert_eq!(
+            reserve_bandwidth("ft_room_bandwidth_other", 500, 1000, now),
+            Duration::ZERO
+        );
+    }
+
+    #[tokio::test]
+    async fn test_offer_download_limit() {
+        let room = "ft_room_once";
+        let conn = 9117u64;
+        let mut rx = register_test_connection(room, conn).await;
+
+        handle_client_op(
+            room,
+            conn,
+            r#"{"op":"offer","name":"once.bin","size":1,"maxDownloads":2,"password":"s3cret"}"#,
+        )
+        .await;
+        let ok = next_control(&mut rx).await;
+        let file_id = ok["fileId"].as_str().unwrap().to_string();
+        let added = next_control(&mut rx).await;
+        assert_eq!(added["file"]["protected"], true);
+        assert_eq!(added["file"]["maxDownloads"], 2);
+        assert!(
+            added["file"].get("password").is_none(),
+            "口令不应出现在元数据里"
+        );
+
+        let offer = FILE_OFFERS.get(&file_id).unwrap().value().clone();
+        assert_eq!(offer.access.password.as_deref(), Some("s3cret"));
+
+        assert_eq!(take_download(&file_id), Some(Some(1)));
+        assert_eq!(take_download(&file_id), Some(Some(0)));
+        assert_eq!(take_download(&file_id), None, "次数用尽后不能再下载");
+
+        cleanup_room(room).await;
+    }
+
+    #[tokio::test]
+    async fn test_sign_link_holder_only() {
+        let room = "ft_room_sign";
+        let holder = 9118u64;
+        let other = 9119u64;
+        let mut holder_rx = register_test_connection(room, holder).await;
+        let mut other_rx = register_test_connection(room, other).await;
+
+        handle_client_op(
+            room,
+            holder,
+            r#"{"op":"offer","name":"s.bin","size":1,"signedOnly":true}"#,
+        )
+        .await;
+        let file_id = next_control(&mut holder_rx).await["fileId"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let _ = next_control(&mut holder_rx).await; // 消费 offer_added
+        let _ = next_control(&mut other_rx).await; // 消费 offer_added
+
+        let sign = format!(r#"{{"op":"sign_link","fileId":"{file_id}","expiresIn":60}}"#);
+        handle_client_op(room, other, &sign).await;
+        assert_eq!(next_control(&mut other_rx).await["op"], "error");
+
+        handle_client_op(room, holder, &sign).await;
+        let link = next_control(&mut holder_rx).await;
+        assert_eq!(link["op"], "link");
+        let expires = link["expires"].as_u64().unwrap();
+        let url = link["url"].as_str().unwrap();
+        assert!(url.starts_with(&format!("/files/download/{file_id}?expires={expires}&sig=")));
+        let sig = url.rsplit("sig=").next().unwrap();
+        assert!(links::verify(&file_id, expires, sig, expires - 60));
+
+        cleanup_room(room).await;
+    }
+
+    #[test]
+    fn test_link_signature() {
+        let sig = links::sign("file1", 2_000);
+        assert!(links::verify("file1", 2_000, &sig, 1_000));
+        assert!(!links::verify("file1", 2_000, &sig, 2_000), "到期后失效");
+        assert!(!links::verify("file2", 2_000, &sig, 1_000), "不同文件签名无效");
+        assert!(!links::verify("file1", 3_000, &sig, 1_000), "篡改过期时间无效");
+        assert!(!links::verify("file1", 2_000, "zz", 1_000));
+        assert!(links::constant_time_eq(b"abc", b"abc"));
+        assert!(!links::constant_time_eq(b"abc", b"abd"));
+        assert!(!links::constant_time_eq(b"abc", b"ab"));
+    }
+
     #[test]
     fn test_percent_encode() {
         assert_eq!(files::percent_encode("a b.png"), "a%20b.png");
@@ -0,0 +1,153 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// 单个归档的上限：不写 ZIP64 扩展，偏移与长度都必须落在 u32 内
+pub const MAX_ZIP_BYTES: u64 = u32::MAX as u64;
+pub const MAX_ZIP_ENTRIES: usize = u16::MAX as usize;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x0605_4b50;
+const VERSION: u16 = 20;
+/// bit 3：CRC 与长度写在数据描述符里；bit 11：文件名为 UTF-8
+const FLAGS: u16 = 0x0808;
+const METHOD_STORED: u16 = 0;
+/// DOS 日期 1980-01-01，成员不携带真实时间
+const DOS_DATE: u16 = 0x0021;
+const DOS_TIME: u16 = 0;
+
+struct EntryRecord {
+    name: Vec<u8>,
+    crc: u32,
+    size: u64,
+    offset: u64,
+}
+
+/// 流式 ZIP 编码器（stored，不压缩）：成员数据边到边写，
+/// 每个成员的 CRC 与长度在数据结束后通过数据描述符补上
+#[derive(Default)]
+pub struct ZipStream {
+    entries: Vec<EntryRecord>,
+    offset: u64,
+    current: Option<EntryRecord>,
+}
+
+impl ZipStream {
+    /// 开始一个新成员，返回其本地文件头
+    pub fn start_entry(&mut self, name: &str) -> Bytes {
+        let name = name.as_bytes().to_vec();
+        let mut buf = BytesMut::with_capacity(30 + name.len());
+        buf.put_u32_le(LOCAL_HEADER_SIGNATURE);
+        buf.put_u16_le(VERSION);
+        buf.put_u16_le(FLAGS);
+        buf.put_u16_le(METHOD_STORED);
+        buf.put_u16_le(DOS_TIME);
+        buf.put_u16_le(DOS_DATE);
+        buf.put_u32_le(0);
+        buf.put_u32_le(0);
+        buf.put_u32_le(0);
+        buf.put_u16_le(name.len() as u16);
+        buf.put_u16_le(0);
+        buf.put_slice(&name);
+
+        self.current = Some(EntryRecord {
+            name,
+            crc: CRC_INIT,
+            size: 0,
+            offset: self.offset,
+        });
+        self.offset += buf.len() as u64;
+        buf.freeze()
+    }
+
+    /// 累计当前成员的数据；归档超出 u32 上限时返回 false
+    pub fn entry_data(&mut self, data: &[u8]) -> bool {
+        let Some(entry) = self.current.as_mut() else {
+            return false;
+        };
+        entry.crc = crc32_update(entry.crc, data);
+        entry.size += data.len() as u64;
+        self.offset += data.len() as u64;
+        self.offset <= MAX_ZIP_BYTES
+    }
+
+    /// 结束当前成员，返回数据描述符
+    pub fn finish_entry(&mut self) -> Bytes {
+        let Some(mut entry) = self.current.take() else {
+            return Bytes::new();
+        };
+        entry.crc = !entry.crc;
+        let mut buf = BytesMut::with_capacity(16);
+        buf.put_u32_le(DATA_DESCRIPTOR_SIGNATURE);
+        buf.put_u32_le(entry.crc);
+        buf.put_u32_le(entry.size as u32);
+        buf.put_u32_le(entry.size as u32);
+        self.offset += buf.len() as u64;
+        self.entries.push(entry);
+        buf.freeze()
+    }
+
+    /// 写出中央目录与目录结束记录
+    pub fn finish(self) -> Bytes {
+        let mut buf = BytesMut::new();
+        for entry in &self.entries {
+            buf.put_u32_le(CENTRAL_HEADER_SIGNATURE);
+            buf.put_u16_le(VERSION);
+            buf.put_u16_le(VERSION);
+            buf.put_u16_le(FLAGS);
+            buf.put_u16_le(METHOD_STORED);
+            buf.put_u16_le(DOS_TIME);
+            buf.put_u16_le(DOS_DATE);
+            buf.put_u32_le(entry.crc);
+            buf.put_u32_le(entry.size as u32);
+            buf.put_u32_le(entry.size as u32);
+            buf.put_u16_le(entry.name.len() as u16);
+            buf.put_u16_le(0);
+            buf.put_u16_le(0);
+            buf.put_u16_le(0);
+            buf.put_u16_le(0);
+            buf.put_u32_le(0);
+            buf.put_u32_le(entry.offset as u32);
+            buf.put_slice(&entry.name);
+        }
+        let central_dir_size = buf.len() as u32;
+        buf.put_u32_le(END_OF_CENTRAL_DIR_SIGNATURE);
+        buf.put_u16_le(0);
+        buf.put_u16_le(0);
+        buf.put_u16_le(self.entries.len() as u16);
+        buf.put_u16_le(self.entries.len() as u16);
+        buf.put_u32_le(central_dir_size);
+        buf.put_u32_le(self.offset as u32);
+        buf.put_u16_le(0);
+        buf.freeze()
+    }
+}
+
+const CRC_INIT: u32 = 0xFFFF_FFFF;
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}